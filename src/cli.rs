@@ -0,0 +1,115 @@
+// Command-line arguments.
+use std::error::Error;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: lpx-controller [OPTIONS]
+
+Options:
+  -c, --config <FILE>         Read configuration from FILE
+  -n, --client-name <NAME>    ALSA sequencer client name
+  -d, --device-port <PORT>    Launchpad X port to connect to, as 'client:port'
+  -s, --serial <SERIAL>       Serial number of the Launchpad X to connect to
+  -v, --verbose               Print incoming events
+  -l, --list-ports            List Launchpad X sequencer ports and exit
+  -V, --version               Print version and exit
+  -h, --help                  Print this help and exit";
+
+#[derive(Default)]
+pub struct Args {
+    pub config: Option<PathBuf>,
+    pub client_name: Option<String>,
+    pub device_port: Option<String>,
+    pub serial: Option<String>,
+    pub verbose: bool,
+    pub list_ports: bool,
+    pub version: bool,
+    pub help: bool,
+}
+
+impl Args {
+    // Parse arguments (without the program name).
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Self, Box<dyn Error>> {
+        let mut parsed = Args::default();
+
+        while let Some(arg) = args.next() {
+            // Support both "--option value" and "--option=value".
+            let (name, mut value) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => (name.to_string(), Some(value.to_string())),
+                _ => (arg, None),
+            };
+            let mut take_value = || value.take().or_else(|| args.next())
+                .ok_or_else(|| format!("missing value for {}", name));
+
+            match name.as_str() {
+                "-c" | "--config" => parsed.config = Some(PathBuf::from(take_value()?)),
+                "-n" | "--client-name" => parsed.client_name = Some(take_value()?),
+                "-d" | "--device-port" => {
+                    let port = take_value()?;
+                    if port.split_once(':').is_none() {
+                        return Err(format!("--device-port must be 'client:port', got '{}'", port).into());
+                    }
+                    parsed.device_port = Some(port);
+                },
+                "-s" | "--serial" => parsed.serial = Some(take_value()?),
+                "-v" | "--verbose" => parsed.verbose = true,
+                "-l" | "--list-ports" => parsed.list_ports = true,
+                "-V" | "--version" => parsed.version = true,
+                "-h" | "--help" => parsed.help = true,
+                _ => return Err(format!("unknown argument '{}', see --help", name).into()),
+            }
+
+            if value.is_some() {
+                return Err(format!("{} does not take a value", name).into());
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Args {
+        match Args::parse(args.iter().map(|arg| arg.to_string())) {
+            Ok(args) => args,
+            Err(err) => panic!("{:?}: {}", args, err),
+        }
+    }
+
+    fn error(args: &[&str]) -> String {
+        match Args::parse(args.iter().map(|arg| arg.to_string())) {
+            Ok(_) => panic!("{:?} was accepted", args),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn parse_args() {
+        let args = parse(&[]);
+        assert!(args.config.is_none() && args.client_name.is_none() && args.device_port.is_none() && args.serial.is_none());
+        assert!(!args.verbose && !args.list_ports && !args.version && !args.help);
+
+        let args = parse(&["-c", "lpx.toml", "--client-name", "LPX", "-d", "Launchpad X:Launchpad X MIDI 1", "--serial=LPX1234"]);
+        assert_eq!(args.config, Some(PathBuf::from("lpx.toml")));
+        assert_eq!(args.client_name.as_deref(), Some("LPX"));
+        assert_eq!(args.device_port.as_deref(), Some("Launchpad X:Launchpad X MIDI 1"));
+        assert_eq!(args.serial.as_deref(), Some("LPX1234"));
+        assert_eq!(parse(&["--config=a=b.toml"]).config, Some(PathBuf::from("a=b.toml")));
+
+        let args = parse(&["-v", "--list-ports", "-V", "--help"]);
+        assert!(args.verbose && args.list_ports && args.version && args.help);
+    }
+
+    #[test]
+    fn parse_args_errors() {
+        assert_eq!(error(&["--config"]), "missing value for --config");
+        assert_eq!(error(&["-n"]), "missing value for -n");
+        assert_eq!(error(&["--device-port", "Launchpad X"]), "--device-port must be 'client:port', got 'Launchpad X'");
+        assert_eq!(error(&["--frobnicate"]), "unknown argument '--frobnicate', see --help");
+        assert_eq!(error(&["-x"]), "unknown argument '-x', see --help");
+        assert_eq!(error(&["--verbose=yes"]), "--verbose does not take a value");
+    }
+}
@@ -0,0 +1,755 @@
+// Configuration file, so that CCs, channels, colors and port names can be changed without a fork.
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+extern crate serde;
+use serde::{Deserialize, Deserializer};
+use serde::de::Error as _;
+
+use crate::lpxproc::{LPXColor, LPXFader, LPXLight, LPXPolarity, LpxButton, NoteOrCtrl, LPX_MIXER_CHANNEL};
+use crate::lpxproc::LPXColor::*;
+use crate::profile::{find_profile, PROFILES};
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub ports: PortsConfig,
+    pub mixer: MixerConfig,
+    pub programmer: ProgrammerConfig,
+    // Button ctrls that we need to store because we use them (e.g. in the mixer views).
+    pub stored_buttons: Vec<u32>,
+    // Time (in milliseconds) after a value was sent to the Controller, in which the same controller
+    // coming back from it is not passed to the Launchpad X (so that e.g. a fader doesn't stop fading).
+    pub echo_window: u64,
+    // Time (in milliseconds) in which an LED gets at most one update from the Controller, more are
+    // combined into the last one, and the number of LED messages per second sent to the Launchpad X.
+    pub led_window: u64,
+    pub led_rate: u32,
+    // Launchpad X devices, when there is more than one (otherwise the device from ports is used).
+    pub devices: Vec<DeviceConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PortsConfig {
+    pub client_name: String,
+    // Client and port name of the device, separated by a colon, or empty to find it automatically.
+    pub device: String,
+    // MIDI port of the device, when device is given.
+    pub device_midi: String,
+    // Serial number of the device to find, or empty for the first Launchpad.
+    pub serial: String,
+    // Kind of device (see profile.rs), or empty for any (or the Launchpad X with a fixed device).
+    pub model: String,
+    pub launchpad_in: String,
+    pub launchpad_out: String,
+    pub launchpad_midi_in: String,
+    pub controller_in: String,
+    pub controller_out: String,
+    // Notes and custom layout data from the Launchpad X MIDI port.
+    pub controller_midi_out: String,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeviceConfig {
+    // Suffix for the port names of this device.
+    pub name: String,
+    // Same as in PortsConfig.
+    pub device: String,
+    pub device_midi: String,
+    pub serial: String,
+    pub model: String,
+    // Layouts that can be selected on this device, the first one is selected at startup.
+    pub layouts: Vec<Layout>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    Session,
+    Mixer,
+    Note,
+    Custom,
+    Programmer,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MixerConfig {
+    // Channel on which the mixer controls are sent to and received from the Controller.
+    pub channel: u8,
+    // Banks of eight tracks, which the arrow buttons page through (none is a single bank).
+    pub track_banks: Vec<TrackBankConfig>,
+    pub volume: FaderBankConfig,
+    pub pan: FaderBankConfig,
+    pub send_a: FaderBankConfig,
+    pub send_b: FaderBankConfig,
+    pub stop_clip: ToggleBankConfig,
+    pub mute: ToggleBankConfig,
+    pub solo: ToggleBankConfig,
+    pub record_arm: ToggleBankConfig,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FaderBankConfig {
+    // First CC of the eight faders.
+    pub ctrl: u8,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub color: LPXColor,
+    // Polarity of the faders, when not the default of the mixer layout.
+    #[serde(default)]
+    pub polarity: Option<Polarity>,
+    // Settings of each fader (from the left or top), for those that differ from the bank.
+    #[serde(default)]
+    pub faders: Vec<FaderConfig>,
+}
+
+// Eight toggles on the bottom row of pads, sending either CCs or notes.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ToggleBankConfig {
+    // First CC of the eight toggles.
+    #[serde(default)]
+    pub ctrl: Option<u8>,
+    // First note of the eight toggles, instead of ctrl.
+    #[serde(default)]
+    pub note: Option<u8>,
+    // Color when on.
+    #[serde(deserialize_with = "deserialize_color")]
+    pub color: LPXColor,
+    // Color when off.
+    #[serde(default = "default_color_off", deserialize_with = "deserialize_color")]
+    pub color_off: LPXColor,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FaderConfig {
+    pub ctrl: Option<u8>,
+    #[serde(deserialize_with = "deserialize_some_color")]
+    pub color: Option<LPXColor>,
+    pub polarity: Option<Polarity>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Polarity {
+    Unipolar,
+    Bipolar,
+}
+
+impl From<Polarity> for LPXPolarity {
+    fn from(polarity: Polarity) -> Self {
+        match polarity {
+            Polarity::Unipolar => LPXPolarity::Unipolar,
+            Polarity::Bipolar => LPXPolarity::Bipolar,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TrackBankConfig {
+    // Channel of the mixer control changes of this bank, when not mixer.channel.
+    pub channel: Option<u8>,
+    // Added to the CCs of each fader bank.
+    #[serde(default)]
+    pub offset: u8,
+    // Color of the selected mixer button, to tell the banks apart.
+    #[serde(default, deserialize_with = "deserialize_some_color")]
+    pub color: Option<LPXColor>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProgrammerConfig {
+    // Pages of the programmer layout, which the right column buttons select (from the top).
+    pub pages: Vec<ProgrammerPageConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProgrammerPageConfig {
+    // Color of the button of the page.
+    #[serde(default = "default_page_color", deserialize_with = "deserialize_color")]
+    pub color: LPXColor,
+    #[serde(default)]
+    pub pads: Vec<ProgrammerPadConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProgrammerPadConfig {
+    // Position of the pad, from the bottom left (0-7).
+    pub x: u8,
+    pub y: u8,
+    // Either a CC or a note towards the Controller, on channel.
+    pub ctrl: Option<u8>,
+    pub note: Option<u8>,
+    #[serde(default = "default_pad_channel")]
+    pub channel: u8,
+    // Color when pressed (or switched on), and when released (or switched off), RGB values are shown exactly.
+    #[serde(deserialize_with = "deserialize_light")]
+    pub color: LPXLight,
+    #[serde(default = "default_light_off", deserialize_with = "deserialize_light")]
+    pub color_off: LPXLight,
+    // Switch on and off with each press, instead of sending both the press and the release.
+    #[serde(default)]
+    pub toggle: bool,
+}
+
+// A track bank with the defaults filled in.
+pub struct TrackBank {
+    pub channel: u8,
+    pub offset: u8,
+    pub color: LPXColor,
+}
+
+// Colors of the selected mixer button for banks without a color.
+const TRACK_BANK_COLORS: [LPXColor; 8] = [Softgreen, Sky, Violet, Pink, Yellow, Turquoise, Lightorange, Lime];
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            ports: PortsConfig::default(),
+            mixer: MixerConfig::default(),
+            programmer: ProgrammerConfig::default(),
+            stored_buttons: (0..8).filter_map(LpxButton::scene).map(LpxButton::ctrl).collect(),
+            echo_window: 500,
+            led_window: 20,
+            led_rate: 400,
+            devices: vec![],
+        }
+    }
+}
+
+impl Default for PortsConfig {
+    fn default() -> Self {
+        PortsConfig {
+            client_name: "Launchpad X Controller".to_string(),
+            device: "".to_string(),
+            device_midi: "".to_string(),
+            serial: "".to_string(),
+            model: "".to_string(),
+            launchpad_in: "Lauchpad X in".to_string(),
+            launchpad_out: "Launchpad X out".to_string(),
+            launchpad_midi_in: "Launchpad X MIDI in".to_string(),
+            controller_in: "Controller in".to_string(),
+            controller_out: "Controller out".to_string(),
+            controller_midi_out: "Controller MIDI out".to_string(),
+        }
+    }
+}
+
+impl Default for DeviceConfig {
+    fn default() -> Self {
+        DeviceConfig {
+            name: "".to_string(),
+            device: "".to_string(),
+            device_midi: "".to_string(),
+            serial: "".to_string(),
+            model: "".to_string(),
+            layouts: vec![Layout::Session, Layout::Mixer, Layout::Note, Layout::Custom],
+        }
+    }
+}
+
+impl Default for MixerConfig {
+    fn default() -> Self {
+        MixerConfig {
+            channel: LPX_MIXER_CHANNEL,
+            track_banks: vec![],
+            volume: FaderBankConfig { ctrl: 30, color: Orange, polarity: None, faders: vec![] },
+            pan: FaderBankConfig { ctrl: 38, color: Yellow, polarity: None, faders: vec![] },
+            send_a: FaderBankConfig { ctrl: 46, color: Green, polarity: None, faders: vec![] },
+            send_b: FaderBankConfig { ctrl: 54, color: Blue, polarity: None, faders: vec![] },
+            stop_clip: ToggleBankConfig { ctrl: Some(62), note: None, color: Pink, color_off: default_color_off() },
+            mute: ToggleBankConfig { ctrl: Some(70), note: None, color: Yellow, color_off: default_color_off() },
+            solo: ToggleBankConfig { ctrl: Some(78), note: None, color: Blue, color_off: default_color_off() },
+            record_arm: ToggleBankConfig { ctrl: Some(86), note: None, color: Red, color_off: default_color_off() },
+        }
+    }
+}
+
+impl FaderBankConfig {
+    // Controller numbers of the eight faders.
+    pub fn ctrls(&self) -> Vec<u32> {
+        (0..8).map(|i| match self.faders.get(i).and_then(|fader| fader.ctrl) {
+            Some(ctrl) => ctrl as u32,
+            None => self.ctrl as u32 + i as u32,
+        }).collect()
+    }
+
+    // The eight faders, with polarity as the default.
+    pub fn lpx_faders(&self, polarity: LPXPolarity) -> Vec<LPXFader> {
+        let polarity = self.polarity.map_or(polarity, LPXPolarity::from);
+        self.ctrls().iter().enumerate().map(|(i, ctrl)| {
+            let fader = self.faders.get(i);
+            LPXFader {
+                polarity: fader.and_then(|fader| fader.polarity).map_or(polarity, LPXPolarity::from),
+                ctrl: *ctrl as u8,
+                color: fader.and_then(|fader| fader.color).unwrap_or(self.color),
+            }
+        }).collect()
+    }
+}
+
+impl MixerConfig {
+    // The track banks, which is a single bank on the mixer channel when none are configured.
+    pub fn track_banks(&self) -> Vec<TrackBank> {
+        if self.track_banks.is_empty() {
+            return vec![TrackBank { channel: self.channel, offset: 0, color: TRACK_BANK_COLORS[0] }];
+        }
+        self.track_banks.iter().enumerate().map(|(i, bank)| TrackBank {
+            channel: bank.channel.unwrap_or(self.channel),
+            offset: bank.offset,
+            color: bank.color.unwrap_or(TRACK_BANK_COLORS[i % TRACK_BANK_COLORS.len()]),
+        }).collect()
+    }
+
+    // Fader banks by name.
+    pub fn fader_banks(&self) -> [(&'static str, &FaderBankConfig); 4] {
+        [
+            ("volume", &self.volume),
+            ("pan", &self.pan),
+            ("send_a", &self.send_a),
+            ("send_b", &self.send_b),
+        ]
+    }
+
+    // Toggle banks by name.
+    pub fn toggle_banks(&self) -> [(&'static str, &ToggleBankConfig); 4] {
+        [
+            ("stop_clip", &self.stop_clip),
+            ("mute", &self.mute),
+            ("solo", &self.solo),
+            ("record_arm", &self.record_arm),
+        ]
+    }
+}
+
+impl ToggleBankConfig {
+    pub fn is_note(&self) -> bool {
+        self.note.is_some()
+    }
+
+    // CCs or notes of the eight toggles.
+    pub fn numbers(&self) -> Vec<u32> {
+        let first = self.note.or(self.ctrl).unwrap_or(0) as u32;
+        (first..first + 8).collect()
+    }
+}
+
+impl ProgrammerPadConfig {
+    pub fn number(&self) -> NoteOrCtrl {
+        match self.note {
+            Some(note) => NoteOrCtrl::Note(note),
+            None => NoteOrCtrl::Ctrl(self.ctrl.unwrap_or(0) as u32),
+        }
+    }
+}
+
+impl DeviceConfig {
+    pub fn has_layout(&self, layout: Layout) -> bool {
+        self.layouts.contains(&layout)
+    }
+
+    // Name of one of our ports for this device.
+    pub fn port_name(&self, port: &str) -> String {
+        if self.name.is_empty() { port.to_string() } else { format!("{} {}", port, self.name) }
+    }
+
+    fn validate(&self, section: &str) -> Result<(), Box<dyn Error>> {
+        if !self.device.is_empty() && self.device.split_once(':').is_none() {
+            return Err(format!("{}.device must be 'client:port', got '{}'", section, self.device).into());
+        }
+        if !self.device_midi.is_empty() && (self.device.is_empty() || self.device_midi.split_once(':').is_none()) {
+            return Err(format!("{}.device_midi must be 'client:port' and needs {}.device, got '{}'", section, section, self.device_midi).into());
+        }
+        if !self.device.is_empty() && !self.serial.is_empty() {
+            return Err(format!("{}.device and {}.serial can't be used together", section, section).into());
+        }
+        if !self.model.is_empty() && find_profile(&self.model).is_none() {
+            let models: Vec<_> = PROFILES.iter().map(|profile| profile.model()).collect();
+            return Err(format!("{}.model must be one of {}, got '{}'", section, models.join(", "), self.model).into());
+        }
+        if self.layouts.is_empty() {
+            return Err(format!("{}.layouts can't be empty", section).into());
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    // The devices to run, which is the device from the ports section when no devices are configured.
+    pub fn devices(&self) -> Vec<DeviceConfig> {
+        if !self.devices.is_empty() { return self.devices.clone(); }
+        vec![DeviceConfig {
+            device: self.ports.device.clone(),
+            device_midi: self.ports.device_midi.clone(),
+            serial: self.ports.serial.clone(),
+            model: self.ports.model.clone(),
+            ..DeviceConfig::default()
+        }]
+    }
+
+    // Load the configuration file from the default location, if it exists.
+    pub fn load_default() -> Result<Self, Box<dyn Error>> {
+        match default_path() {
+            Some(path) if path.exists() => Self::load(&path),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    // Load and validate a configuration file.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+        let config: Config = toml::from_str(&contents)
+            .map_err(|e| format!("could not parse {}: {}", path.display(), e))?;
+        config.validate()
+            .map_err(|e| format!("invalid configuration in {}: {}", path.display(), e))?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), Box<dyn Error>> {
+        let ports = &self.ports;
+        let ports_device = !ports.device.is_empty() || !ports.device_midi.is_empty() || !ports.serial.is_empty();
+        if !self.devices.is_empty() && ports_device {
+            return Err("ports.device, ports.device_midi and ports.serial can't be used together with devices".into());
+        }
+        for (i, device) in self.devices().iter().enumerate() {
+            let section = if self.devices.is_empty() { "ports".to_string() } else { format!("devices[{}]", i) };
+            device.validate(&section)?;
+            if self.devices.len() < 2 { continue; }
+            // Each device needs its own ports, and a way to tell it apart from the others.
+            if device.name.is_empty() || self.devices.iter().filter(|other| other.name == device.name).count() > 1 {
+                return Err(format!("{}.name must be set and unique", section).into());
+            }
+            if device.device.is_empty() && device.serial.is_empty() {
+                return Err(format!("{} needs a device or serial", section).into());
+            }
+        }
+
+        if self.led_window == 0 || self.led_rate == 0 {
+            return Err("led_window and led_rate must be more than 0".into());
+        }
+
+        // Channels 1-3 are used for the Launchpad X buttons and LEDs.
+        if !(4..=16).contains(&self.mixer.channel) {
+            return Err(format!("mixer.channel must be between 4 and 16, got {}", self.mixer.channel).into());
+        }
+
+        let banks = self.mixer.fader_banks();
+        for (name, bank) in banks.iter() {
+            if bank.faders.len() > 8 {
+                return Err(format!("mixer.{}.faders can have at most 8 faders, got {}", name, bank.faders.len()).into());
+            }
+            if let Some(ctrl) = bank.ctrls().iter().find(|ctrl| **ctrl > 127) {
+                return Err(format!("mixer.{} has CC {}, which is past 127 (check mixer.{}.ctrl)", name, ctrl, name).into());
+            }
+        }
+        for (i, (name, bank)) in banks.iter().enumerate() {
+            let ctrls = bank.ctrls();
+            if ctrls.iter().enumerate().any(|(j, ctrl)| ctrls[..j].contains(ctrl)) {
+                return Err(format!("mixer.{} uses the same CC for more than one fader", name).into());
+            }
+            for (other_name, other_bank) in banks.iter().skip(i + 1) {
+                if other_bank.ctrls().iter().any(|ctrl| ctrls.contains(ctrl)) {
+                    return Err(format!("mixer.{} and mixer.{} have overlapping CCs", name, other_name).into());
+                }
+            }
+        }
+
+        for (name, bank) in self.mixer.toggle_banks().iter() {
+            if bank.ctrl.is_some() == bank.note.is_some() {
+                return Err(format!("mixer.{} needs either ctrl or note", name).into());
+            }
+            if bank.numbers()[7] > 127 {
+                return Err(format!("mixer.{}.{} must be between 0 and 120", name, if bank.is_note() { "note" } else { "ctrl" }).into());
+            }
+        }
+
+        // Each track bank needs its own CCs (and notes), which still fit in 0-127.
+        let mut ctrls = vec![];
+        for (i, track_bank) in self.mixer.track_banks().iter().enumerate() {
+            if !(4..=16).contains(&track_bank.channel) {
+                return Err(format!("mixer.track_banks[{}].channel must be between 4 and 16, got {}", i, track_bank.channel).into());
+            }
+            for (name, bank) in banks.iter() {
+                if bank.ctrls().iter().any(|ctrl| ctrl + track_bank.offset as u32 > 127) {
+                    return Err(format!("mixer.track_banks[{}].offset moves mixer.{} past CC 127", i, name).into());
+                }
+                for ctrl in bank.ctrls() {
+                    let ctrl = (track_bank.channel, false, ctrl + track_bank.offset as u32);
+                    if ctrls.contains(&ctrl) {
+                        return Err(format!("mixer.track_banks[{}] has CCs that overlap with another bank", i).into());
+                    }
+                    ctrls.push(ctrl);
+                }
+            }
+            for (name, bank) in self.mixer.toggle_banks().iter() {
+                if bank.numbers()[7] + track_bank.offset as u32 > 127 {
+                    return Err(format!("mixer.track_banks[{}].offset moves mixer.{} past 127", i, name).into());
+                }
+                for number in bank.numbers() {
+                    let number = (track_bank.channel, bank.is_note(), number + track_bank.offset as u32);
+                    if ctrls.contains(&number) {
+                        return Err(format!("mixer.{} overlaps with other CCs or notes of the mixer", name).into());
+                    }
+                    ctrls.push(number);
+                }
+            }
+        }
+
+        if self.programmer.pages.len() > 8 {
+            return Err(format!("programmer.pages can have at most 8 pages, got {}", self.programmer.pages.len()).into());
+        }
+        for (i, page) in self.programmer.pages.iter().enumerate() {
+            for (j, pad) in page.pads.iter().enumerate() {
+                let section = format!("programmer.pages[{}].pads[{}]", i, j);
+                if pad.x > 7 || pad.y > 7 {
+                    return Err(format!("{} must have x and y between 0 and 7", section).into());
+                }
+                if page.pads[..j].iter().any(|other| (other.x, other.y) == (pad.x, pad.y)) {
+                    return Err(format!("{} is at the same place as another pad of the page", section).into());
+                }
+                if pad.ctrl.is_some() == pad.note.is_some() {
+                    return Err(format!("{} needs either ctrl or note", section).into());
+                }
+                if pad.ctrl.or(pad.note).unwrap_or(0) > 127 {
+                    return Err(format!("{} must have a ctrl or note between 0 and 127", section).into());
+                }
+                if !(1..=16).contains(&pad.channel) {
+                    return Err(format!("{}.channel must be between 1 and 16, got {}", section, pad.channel).into());
+                }
+            }
+        }
+
+        // Without a model, the buttons must exist on any device that may be found.
+        for device in self.devices().iter() {
+            let profiles = find_profile(&device.model).map_or(PROFILES.to_vec(), |profile| vec![profile]);
+            for button in self.stored_buttons.iter() {
+                if let Some(profile) = profiles.iter().find(|profile| !profile.is_button(*button)) {
+                    return Err(format!("stored_buttons contains {}, which is not a {} button", button, profile.name()).into());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn default_color_off() -> LPXColor {
+    Darkgrey
+}
+
+fn default_light_off() -> LPXLight {
+    LPXLight::Static(Darkgrey as u8)
+}
+
+fn default_page_color() -> LPXColor {
+    Magenta
+}
+
+fn default_pad_channel() -> u8 {
+    1
+}
+
+fn default_path() -> Option<PathBuf> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join("lpx-controller").join("config.toml"))
+}
+
+// A color is a palette index, a color name or an RGB value like "#ff8000".
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColorValue {
+    Index(i64),
+    Name(String),
+}
+
+fn deserialize_color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<LPXColor, D::Error> {
+    match ColorValue::deserialize(deserializer)? {
+        ColorValue::Index(index) if (0..128).contains(&index) => Ok(LPXColor::from_index(index as u8)),
+        ColorValue::Index(index) => Err(D::Error::custom(format!("color index {} is out of range (0-127)", index))),
+        ColorValue::Name(name) => name.parse().map_err(|_| D::Error::custom(format!("unknown color '{}'", name))),
+    }
+}
+
+// Like a color, but an RGB value is kept as it is.
+fn deserialize_light<'de, D: Deserializer<'de>>(deserializer: D) -> Result<LPXLight, D::Error> {
+    match ColorValue::deserialize(deserializer)? {
+        ColorValue::Index(index) if (0..128).contains(&index) => Ok(LPXLight::Static(index as u8)),
+        ColorValue::Index(index) => Err(D::Error::custom(format!("color index {} is out of range (0-127)", index))),
+        ColorValue::Name(name) => name.parse().map_err(|_| D::Error::custom(format!("unknown color '{}'", name))),
+    }
+}
+
+fn deserialize_some_color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<LPXColor>, D::Error> {
+    deserialize_color(deserializer).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(toml: &str) -> Config {
+        match toml::from_str(toml) {
+            Ok(config) => config,
+            Err(err) => panic!("{}: {}", toml, err),
+        }
+    }
+
+    // Error of an invalid configuration.
+    fn error(toml: &str) -> String {
+        match parse(toml).validate() {
+            Ok(_) => panic!("{} was accepted", toml),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn valid_config() {
+        assert!(Config::default().validate().is_ok());
+        assert!(parse("").validate().is_ok());
+        let config = parse(r##"
+            stored_buttons = [19, 91]
+            [ports]
+            serial = "LPX1234"
+            model = "launchpad-mini-mk3"
+            [mixer]
+            channel = 16
+            [mixer.volume]
+            ctrl = 0
+            color = 5
+            [[mixer.volume.faders]]
+            ctrl = 120
+            color = "#ff8000"
+            polarity = "bipolar"
+            [mixer.mute]
+            note = 36
+            color = "Yellow"
+            [[programmer.pages]]
+            [[programmer.pages.pads]]
+            x = 7
+            y = 7
+            note = 127
+            channel = 16
+            color = "green"
+            toggle = true
+            [[programmer.pages.pads]]
+            x = 0
+            y = 0
+            ctrl = 20
+            color = "#ff8000"
+            color_off = 3
+        "##);
+        assert!(config.validate().is_ok());
+        assert_eq!(config.mixer.volume.ctrls(), [120, 1, 2, 3, 4, 5, 6, 7]);
+        assert!(config.mixer.volume.faders[0].color.is_some());
+        assert_eq!(config.mixer.mute.numbers()[0], 36);
+        assert_eq!(config.programmer.pages[0].color, Magenta);
+        assert_eq!(config.programmer.pages[0].pads[1].color, LPXLight::Rgb(127, 64, 0));
+        assert_eq!(config.programmer.pages[0].pads[1].color_off, LPXLight::Static(White as u8));
+        assert_eq!(config.programmer.pages[0].pads[0].number(), NoteOrCtrl::Note(127));
+        assert_eq!(config.programmer.pages[0].pads[0].color, LPXLight::Static(Green as u8));
+        assert_eq!(config.programmer.pages[0].pads[0].color_off, LPXLight::Static(Darkgrey as u8));
+
+        let config = parse(r#"
+            [[devices]]
+            name = "left"
+            device = "Launchpad X:Launchpad X MIDI 1"
+            device_midi = "Launchpad X:Launchpad X MIDI 2"
+            layouts = ["mixer"]
+            [[devices]]
+            name = "right"
+            serial = "LPX5678"
+        "#);
+        assert!(config.validate().is_ok());
+        assert_eq!(config.devices()[1].layouts, [Layout::Session, Layout::Mixer, Layout::Note, Layout::Custom]);
+
+        // Track banks on other channels, or with other CCs.
+        let config = parse("[[mixer.track_banks]]\n[[mixer.track_banks]]\nchannel = 6\ncolor = \"red\"\n[[mixer.track_banks]]\nchannel = 7\noffset = 34");
+        assert!(config.validate().is_ok());
+        let banks: Vec<_> = config.mixer.track_banks().iter().map(|bank| (bank.channel, bank.offset, bank.color)).collect();
+        assert_eq!(banks, [(5, 0, Softgreen), (6, 0, Red), (7, 34, Violet)]);
+    }
+
+    #[test]
+    fn invalid_devices() {
+        assert_eq!(error("[ports]\ndevice = \"Launchpad X\""), "ports.device must be 'client:port', got 'Launchpad X'");
+        assert_eq!(error("[ports]\ndevice_midi = \"Launchpad X:Launchpad X MIDI 2\""),
+            "ports.device_midi must be 'client:port' and needs ports.device, got 'Launchpad X:Launchpad X MIDI 2'");
+        assert_eq!(error("[ports]\ndevice = \"a:b\"\nserial = \"LPX1234\""), "ports.device and ports.serial can't be used together");
+        assert_eq!(error("[ports]\nmodel = \"launchpad-s\""),
+            "ports.model must be one of launchpad-x, launchpad-mini-mk3, launchpad-pro-mk3, got 'launchpad-s'");
+        assert_eq!(error("[ports]\nserial = \"LPX1234\"\n[[devices]]\nserial = \"LPX5678\""),
+            "ports.device, ports.device_midi and ports.serial can't be used together with devices");
+        assert_eq!(error("[[devices]]\nlayouts = []"), "devices[0].layouts can't be empty");
+        assert_eq!(error("[[devices]]\nname = \"a\"\nserial = \"1\"\n[[devices]]\nname = \"a\"\nserial = \"2\""),
+            "devices[0].name must be set and unique");
+        assert_eq!(error("[[devices]]\nserial = \"1\"\n[[devices]]\nname = \"b\"\nserial = \"2\""),
+            "devices[0].name must be set and unique");
+        assert_eq!(error("[[devices]]\nname = \"a\"\nserial = \"1\"\n[[devices]]\nname = \"b\""), "devices[1] needs a device or serial");
+    }
+
+    #[test]
+    fn invalid_mixer() {
+        assert_eq!(error("led_window = 0"), "led_window and led_rate must be more than 0");
+        assert_eq!(error("led_rate = 0"), "led_window and led_rate must be more than 0");
+        assert_eq!(error("[mixer]\nchannel = 3"), "mixer.channel must be between 4 and 16, got 3");
+        let faders = "[[mixer.pan.faders]]\n".repeat(9);
+        assert_eq!(error(&format!("[mixer.pan]\nctrl = 38\ncolor = 1\n{}", faders)), "mixer.pan.faders can have at most 8 faders, got 9");
+        assert_eq!(error("[mixer.send_b]\nctrl = 125\ncolor = 1"), "mixer.send_b has CC 128, which is past 127 (check mixer.send_b.ctrl)");
+        assert_eq!(error("[mixer.volume]\nctrl = 30\ncolor = 1\n[[mixer.volume.faders]]\nctrl = 31"),
+            "mixer.volume uses the same CC for more than one fader");
+        assert_eq!(error("[mixer.pan]\nctrl = 34\ncolor = 1"), "mixer.volume and mixer.pan have overlapping CCs");
+        assert_eq!(error("[mixer.solo]\nctrl = 78\nnote = 36\ncolor = 1"), "mixer.solo needs either ctrl or note");
+        assert_eq!(error("[mixer.solo]\ncolor = 1"), "mixer.solo needs either ctrl or note");
+        assert_eq!(error("[mixer.mute]\nnote = 121\ncolor = 1"), "mixer.mute.note must be between 0 and 120");
+        assert_eq!(error("[mixer.mute]\nctrl = 30\ncolor = 1"), "mixer.mute overlaps with other CCs or notes of the mixer");
+        assert_eq!(error("[[mixer.track_banks]]\nchannel = 3"), "mixer.track_banks[0].channel must be between 4 and 16, got 3");
+        assert_eq!(error("[[mixer.track_banks]]\n[[mixer.track_banks]]\nchannel = 17"), "mixer.track_banks[1].channel must be between 4 and 16, got 17");
+        assert_eq!(error("[[mixer.track_banks]]\n[[mixer.track_banks]]"), "mixer.track_banks[1] has CCs that overlap with another bank");
+        assert_eq!(error("[[mixer.track_banks]]\n[[mixer.track_banks]]\noffset = 7"), "mixer.track_banks[1] has CCs that overlap with another bank");
+        assert_eq!(error("[[mixer.track_banks]]\noffset = 70"), "mixer.track_banks[0].offset moves mixer.send_b past CC 127");
+        assert_eq!(error("[[mixer.track_banks]]\noffset = 35"), "mixer.track_banks[0].offset moves mixer.record_arm past 127");
+        assert_eq!(error("[[mixer.track_banks]]\n[[mixer.track_banks]]\noffset = 35\nchannel = 6"),
+            "mixer.track_banks[1].offset moves mixer.record_arm past 127");
+    }
+
+    #[test]
+    fn invalid_programmer() {
+        let pad = |pad: &str| error(&format!("[[programmer.pages]]\n[[programmer.pages.pads]]\ncolor = 1\n{}", pad));
+        assert_eq!(error(&"[[programmer.pages]]\n".repeat(9)), "programmer.pages can have at most 8 pages, got 9");
+        assert_eq!(pad("x = 8\ny = 0\nctrl = 1"), "programmer.pages[0].pads[0] must have x and y between 0 and 7");
+        assert_eq!(pad("x = 0\ny = 0\nctrl = 1\n[[programmer.pages.pads]]\nx = 0\ny = 0\nctrl = 2\ncolor = 1"),
+            "programmer.pages[0].pads[1] is at the same place as another pad of the page");
+        assert_eq!(pad("x = 0\ny = 0"), "programmer.pages[0].pads[0] needs either ctrl or note");
+        assert_eq!(pad("x = 0\ny = 0\nctrl = 1\nnote = 1"), "programmer.pages[0].pads[0] needs either ctrl or note");
+        assert_eq!(pad("x = 0\ny = 0\nnote = 128"), "programmer.pages[0].pads[0] must have a ctrl or note between 0 and 127");
+        assert_eq!(pad("x = 0\ny = 0\nctrl = 1\nchannel = 0"), "programmer.pages[0].pads[0].channel must be between 1 and 16, got 0");
+    }
+
+    #[test]
+    fn invalid_values() {
+        assert_eq!(error("stored_buttons = [11]"), "stored_buttons contains 11, which is not a Launchpad X button");
+        assert_eq!(error("stored_buttons = [99]"), "stored_buttons contains 99, which is not a Launchpad X button");
+        assert_eq!(error("stored_buttons = [80]\n[ports]\nmodel = \"launchpad-mini-mk3\""),
+            "stored_buttons contains 80, which is not a Launchpad Mini MK3 button");
+        assert!(parse("stored_buttons = [80, 101, 1]\n[ports]\nmodel = \"launchpad-pro-mk3\"").validate().is_ok());
+        assert_eq!(error("stored_buttons = [101]\n[[devices]]\nname = \"a\"\nserial = \"1\"\nmodel = \"launchpad-pro-mk3\"\n[[devices]]\nname = \"b\"\nserial = \"2\""),
+            "stored_buttons contains 101, which is not a Launchpad X button");
+        for toml in ["[mixer.pan]\nctrl = 38\ncolor = 128", "[mixer.pan]\nctrl = 38\ncolor = \"mauve\"", "[mixer.pan]\nctrl = 38\ncolor = \"#ff80\"",
+            "[[programmer.pages]]\n[[programmer.pages.pads]]\nx = 0\ny = 0\nctrl = 1\ncolor = \"#12345g\"",
+            "[ports]\nserail = \"LPX1234\"", "[[devices]]\nlayouts = [\"drums\"]"].iter() {
+            assert!(toml::from_str::<Config>(toml).is_err(), "{}", toml);
+        }
+    }
+}
@@ -1,6 +1,13 @@
 #![allow(non_snake_case)]
 use rmididings::proc::*;
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::profile::{DeviceLayout, DeviceProfile, LaunchpadX};
 
 // Modifier: set output port to Launchpad X
 pub fn LPX() -> Port { Port(1) }
@@ -10,144 +17,2024 @@ pub fn LPXFilter() -> PortFilter { PortFilter(1) }
 pub fn CTR() -> Port { Port(2) }
 // Filter: pass events from the Controller
 pub fn CTRFilter() -> PortFilter { PortFilter(2) }
+// Filter: pass events from our internal notification port
+pub fn INTFilter() -> PortFilter { PortFilter(3) }
+// Filter: pass events from the Launchpad X MIDI port
+pub fn LPXMidiFilter() -> PortFilter { PortFilter(4) }
+// Modifier: set output port to Controller for notes and custom layout data
+pub fn CTRMidi() -> Port { Port(3) }
 
-// Generator: set DAW mode, mode=0 to enable, mode=1 to disable.
-#[macro_export]
-macro_rules! LPXDawMode { ($mode:expr) => {
-    Chain!(SysEx(&[0xf0, 0x00, 0x20, 0x29, 0x02, 0x0c, 0x10, $mode, 0xf7]), LPX())
-} }
+// Notifications sent to our internal port (as a controller number).
+pub const NOTIFY_CONNECTED: u32 = 1;
+pub const NOTIFY_QUIT: u32 = 2;
+pub const NOTIFY_TICK: u32 = 3;
 
-// Generator: select layout, session=0, note=1, custom=4-7, mixers=13, programmer=127.
-#[macro_export]
-macro_rules! LPXSelectLayout { ($layout:expr) => {
-    Chain!(SysEx(&[0xf0, 0x00, 0x20, 0x29, 0x02, 0x0c, 0x00, $layout, 0xf7]), LPX())
-} }
+// Filter: pass a specific notification
+pub fn NotifyFilter<'a>(notification: u32) -> FilterChain<'a> {
+    Chain!(INTFilter(), TypeFilter!(Ctrl), CtrlFilter(notification))
+}
 
-// Generator: setup mixers
-#[macro_export]
-macro_rules! LPXSetupMixers { ($orientation:expr, $polarity:expr, $ctrl:expr, $color:expr) => {
-    Chain!(
-        SysEx(&[0xf0, 0x00, 0x20, 0x29, 0x02, 0x0c, 0x01, 0x00, $orientation as u8,
-            0x00, $polarity as u8, $ctrl+0 as u8, $color as u8,
-            0x01, $polarity as u8, $ctrl+1 as u8, $color as u8,
-            0x02, $polarity as u8, $ctrl+2 as u8, $color as u8,
-            0x03, $polarity as u8, $ctrl+3 as u8, $color as u8,
-            0x04, $polarity as u8, $ctrl+4 as u8, $color as u8,
-            0x05, $polarity as u8, $ctrl+5 as u8, $color as u8,
-            0x06, $polarity as u8, $ctrl+6 as u8, $color as u8,
-            0x07, $polarity as u8, $ctrl+7 as u8, $color as u8,
-            0xf7]),
-        LPX()
-    )
-} }
+// Filter: print events when enabled, passing them unchanged
+pub struct Log(pub bool);
+impl FilterTrait for Log {
+    fn run(&self, evs: &mut EventStream) {
+        if self.0 && !evs.is_empty() {
+            println!("{:?}", evs);
+        }
+    }
+}
+
+// Generator: SysEx with data only known at runtime (SysEx needs static data).
+// The data is never freed, so only use this when building the patch.
+pub fn StaticSysEx(data: Vec<u8>) -> SysEx {
+    SysEx(Box::leak(data.into_boxed_slice()))
+}
 
-#[derive(Copy,Clone)]
+// Channel on which the Launchpad X sends and receives mixer fader values.
+pub const LPX_MIXER_CHANNEL: u8 = 5;
+
+#[derive(Copy,Clone,Debug,PartialEq)]
 pub enum LPXOrientation {
     Vertical = 0,
     Horizontal = 1
 }
 
-#[derive(Copy,Clone)]
+#[derive(Copy,Clone,Debug,PartialEq)]
 pub enum LPXPolarity {
     Unipolar = 0,
     Bipolar = 1
 }
 
-// Generator: set Launchpad X button to a specific color
-pub fn LPXButton<'a>(button: u32, color: LPXColor) -> FilterChain<'a> {
-    Chain!(Ctrl(button, color as i32), Channel(1), LPX())
+impl LPXOrientation {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(LPXOrientation::Vertical),
+            1 => Some(LPXOrientation::Horizontal),
+            _ => None,
+        }
+    }
 }
 
-// Filter: pass events from a specific button press on the Launchpad X
-pub fn LPXButtonFilter<'a>(button: u32) -> FilterChain<'a> {
-    Chain!(LPXFilter(), TypeFilter!(Ctrl), ChannelFilter(1), CtrlFilter(button), CtrlValueFilter(127))
+impl LPXPolarity {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(LPXPolarity::Unipolar),
+            1 => Some(LPXPolarity::Bipolar),
+            _ => None,
+        }
+    }
+}
+
+// A fader in the mixer layouts, see LpxSysEx::SetupFaders.
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub struct LPXFader {
+    pub polarity: LPXPolarity,
+    pub ctrl: u8,
+    pub color: LPXColor,
+}
+
+// Header of all Launchpad SysEx messages, followed by the device byte of the profile.
+pub const SYSEX_HEADER: [u8; 5] = [0xf0, 0x00, 0x20, 0x29, 0x02];
+
+// Launchpad X SysEx messages, as documented in the Launchpad X programmer's reference.
+// The Mini MK3 and Pro MK3 use the same commands, with a different header (see DeviceProfile).
+// The device replies to a query with the same message, which `parse` turns back into a value.
+#[allow(dead_code)]
+#[derive(Clone,Debug,PartialEq)]
+pub enum LpxSysEx {
+    // Select layout, see DeviceProfile::layout.
+    SelectLayout(u8),
+    // Setup the faders of the mixer layouts, the first fader has index 0.
+    SetupFaders(LPXOrientation, Vec<LPXFader>),
+    // Light any number of buttons and pads (11-99).
+    Lighting(Vec<(u8, LPXLight)>),
+    // Velocity curve (0=low, 1=medium, 2=high, 3=fixed) and fixed velocity.
+    VelocityCurve(u8, u8),
+    // Scroll text, color is either LPXLight::Static or LPXLight::Rgb.
+    ScrollText { looping: bool, speed: u8, color: LPXLight, text: String },
+    // Stop scrolling text.
+    StopText,
+    // LED brightness level (0-127).
+    Brightness(u8),
+    // Put the LEDs to sleep (true) or wake them up (false).
+    LedSleep(bool),
+    // Light pads when pressed, for internal (note, custom) and external (DAW) layouts.
+    LedFeedback { internal: bool, external: bool },
+    // Aftertouch (0=polyphonic, 1=channel, 2=off) and threshold (0=low, 1=medium, 2=high).
+    Aftertouch(u8, u8),
+    // Velocity sensitive faders.
+    FaderVelocity(bool),
+    // Programmer mode (true) or live mode (false).
+    ProgrammerMode(bool),
+    // Enable or disable DAW mode.
+    DawMode(bool),
+    // Clear session, drum rack and/or CC state of the DAW mode.
+    ClearDaw { session: bool, drumrack: bool, ctrls: bool },
+    // Session button colors, use active=Black to reset.
+    SessionColor(LPXColor, LPXColor),
+    // Query the setting of a command, e.g. 0x00 for the current layout.
+    Query(u8),
+}
+
+#[allow(dead_code)]
+impl LpxSysEx {
+    // Complete SysEx message for the Launchpad X, including header and end byte.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_device_bytes(&LaunchpadX)
+    }
+
+    // Complete SysEx message for a device, including header and end byte.
+    pub fn to_device_bytes(&self, profile: &dyn DeviceProfile) -> Vec<u8> {
+        let mut data = SYSEX_HEADER.to_vec();
+        data.push(profile.sysex_device());
+        match self {
+            LpxSysEx::SelectLayout(layout) => {
+                data.extend(&[0x00, *layout]);
+                data.extend(profile.layout_suffix());
+            },
+            LpxSysEx::SetupFaders(orientation, faders) => {
+                data.extend(&[0x01, 0x00, *orientation as u8]);
+                for (i, fader) in faders.iter().enumerate() {
+                    data.extend(&[i as u8, fader.polarity as u8, fader.ctrl, fader.color as u8]);
+                }
+            },
+            LpxSysEx::Lighting(leds) => {
+                data.push(0x03);
+                for (led, light) in leds.iter() {
+                    light.push_colorspec(*led, &mut data);
+                }
+            },
+            LpxSysEx::VelocityCurve(curve, fixed) => data.extend(&[0x04, *curve, *fixed]),
+            LpxSysEx::ScrollText { looping, speed, color, text } => {
+                data.extend(&[0x07, *looping as u8, *speed]);
+                match *color {
+                    LPXLight::Rgb(r, g, b) => data.extend(&[0x01, r & 0x7f, g & 0x7f, b & 0x7f]),
+                    LPXLight::Static(color) | LPXLight::Flashing(color, _) | LPXLight::Pulsing(color) =>
+                        data.extend(&[0x00, color & 0x7f]),
+                }
+                data.extend(text.bytes().filter(u8::is_ascii));
+            },
+            LpxSysEx::StopText => data.push(0x07),
+            LpxSysEx::Brightness(level) => data.extend(&[0x08, *level]),
+            LpxSysEx::LedSleep(sleep) => data.extend(&[0x09, !*sleep as u8]),
+            LpxSysEx::LedFeedback { internal, external } => data.extend(&[0x0a, *internal as u8, *external as u8]),
+            LpxSysEx::Aftertouch(mode, threshold) => data.extend(&[0x0b, *mode, *threshold]),
+            LpxSysEx::FaderVelocity(enable) => data.extend(&[0x0d, *enable as u8]),
+            LpxSysEx::ProgrammerMode(enable) => data.extend(&[0x0e, *enable as u8]),
+            LpxSysEx::DawMode(enable) => data.extend(&[0x10, *enable as u8]),
+            LpxSysEx::ClearDaw { session, drumrack, ctrls } =>
+                data.extend(&[0x12, *session as u8, *drumrack as u8, *ctrls as u8]),
+            LpxSysEx::SessionColor(active, inactive) => data.extend(&[0x14, *active as u8, *inactive as u8]),
+            LpxSysEx::Query(command) => data.push(*command),
+        }
+        data.push(0xf7);
+        data
+    }
+
+    // Parse a SysEx message from the Launchpad X, None if it isn't one we know.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        Self::parse_device(&LaunchpadX, data)
+    }
+
+    // Parse a SysEx message from a device, None if it isn't one we know.
+    pub fn parse_device(profile: &dyn DeviceProfile, data: &[u8]) -> Option<Self> {
+        let body = data.strip_prefix(&SYSEX_HEADER[..])?.strip_prefix(&[profile.sysex_device()])?.strip_suffix(&[0xf7])?;
+        let (&command, args) = body.split_first()?;
+        if args.iter().any(|b| b & 0x80 != 0) { return None; }
+        // Text scrolling is stopped with an empty message, the settings are queried with one.
+        if args.is_empty() {
+            return match command {
+                0x07 => Some(LpxSysEx::StopText),
+                0x00 | 0x04 | 0x08..=0x0b | 0x0d | 0x0e | 0x10 | 0x14 => Some(LpxSysEx::Query(command)),
+                _ => None,
+            };
+        }
+        match (command, args) {
+            (0x00, &[layout, ref suffix @ ..]) if suffix == profile.layout_suffix() => Some(LpxSysEx::SelectLayout(layout)),
+            (0x01, &[0x00, orientation, ref faders @ ..]) if faders.len() % 4 == 0 => {
+                let faders = faders.chunks(4).enumerate().map(|(i, fader)| match *fader {
+                    [index, polarity, ctrl, color] if index as usize == i => Some(LPXFader {
+                        polarity: LPXPolarity::from_u8(polarity)?, ctrl, color: LPXColor::from_index(color)
+                    }),
+                    _ => None,
+                }).collect::<Option<Vec<_>>>()?;
+                Some(LpxSysEx::SetupFaders(LPXOrientation::from_u8(orientation)?, faders))
+            },
+            (0x03, mut specs) => {
+                let mut leds = vec![];
+                while !specs.is_empty() {
+                    let (led, rest) = LPXLight::parse_colorspec(specs)?;
+                    leds.push(led);
+                    specs = rest;
+                }
+                Some(LpxSysEx::Lighting(leds))
+            },
+            (0x04, &[curve, fixed]) => Some(LpxSysEx::VelocityCurve(curve, fixed)),
+            (0x07, &[looping, speed, ref rest @ ..]) => {
+                let (color, text) = match *rest {
+                    [0x00, color, ref text @ ..] => (LPXLight::Static(color), text),
+                    [0x01, r, g, b, ref text @ ..] => (LPXLight::Rgb(r, g, b), text),
+                    _ => return None,
+                };
+                let text = String::from_utf8(text.to_vec()).ok()?;
+                Some(LpxSysEx::ScrollText { looping: looping != 0, speed, color, text })
+            },
+            (0x08, &[level]) => Some(LpxSysEx::Brightness(level)),
+            (0x09, &[awake]) => Some(LpxSysEx::LedSleep(awake == 0)),
+            (0x0a, &[internal, external]) => Some(LpxSysEx::LedFeedback { internal: internal != 0, external: external != 0 }),
+            (0x0b, &[mode, threshold]) => Some(LpxSysEx::Aftertouch(mode, threshold)),
+            (0x0d, &[enable]) => Some(LpxSysEx::FaderVelocity(enable != 0)),
+            (0x0e, &[enable]) => Some(LpxSysEx::ProgrammerMode(enable != 0)),
+            (0x10, &[enable]) => Some(LpxSysEx::DawMode(enable != 0)),
+            (0x12, &[session, drumrack, ctrls]) =>
+                Some(LpxSysEx::ClearDaw { session: session != 0, drumrack: drumrack != 0, ctrls: ctrls != 0 }),
+            (0x14, &[active, inactive]) =>
+                Some(LpxSysEx::SessionColor(LPXColor::from_index(active), LPXColor::from_index(inactive))),
+            _ => None,
+        }
+    }
+}
+
+// Generator: send a SysEx message to the device.
+pub fn LPXSysEx<'a>(profile: &dyn DeviceProfile, message: LpxSysEx) -> FilterChain<'a> {
+    Chain!(StaticSysEx(message.to_device_bytes(profile)), LPX())
+}
+
+// Generator: enable or disable DAW mode.
+pub fn LPXDawMode<'a>(profile: &dyn DeviceProfile, enable: bool) -> FilterChain<'a> {
+    LPXSysEx(profile, LpxSysEx::DawMode(enable))
+}
+
+// Generator: select layout.
+pub fn LPXSelectLayout<'a>(profile: &dyn DeviceProfile, layout: DeviceLayout) -> FilterChain<'a> {
+    LPXSysEx(profile, LpxSysEx::SelectLayout(profile.layout(layout)))
+}
+
+// Generator: setup mixers, each fader with its own polarity, CC and color (faders the device
+// doesn't have are left out).
+pub fn LPXSetupFaders<'a>(profile: &dyn DeviceProfile, orientation: LPXOrientation, faders: &[LPXFader]) -> FilterChain<'a> {
+    let faders = faders.iter().take(profile.faders() as usize).copied().collect();
+    LPXSysEx(profile, LpxSysEx::SetupFaders(orientation, faders))
 }
 
 // Generator: set session button colors. Use active=Black to reset.
-#[macro_export]
-macro_rules! LPXSessionColor { ($active:expr, $inactive:expr) => {
-    Chain!(SysEx(&[0xf0, 0x00, 0x20, 0x29, 0x02, 0x0c, 0x14, $active as u8, $inactive as u8, 0xf7]), LPX())
+pub fn LPXSessionColor<'a>(profile: &dyn DeviceProfile, active: LPXColor, inactive: LPXColor) -> FilterChain<'a> {
+    LPXSysEx(profile, LpxSysEx::SessionColor(active, inactive))
+}
+
+// Universal device inquiry, answered by any MIDI device with its identity, see DeviceInfo.
+pub const DEVICE_INQUIRY: [u8; 6] = [0xf0, 0x7e, 0x7f, 0x06, 0x01, 0xf7];
+
+// Novation's manufacturer ID, as in a device inquiry reply.
+const NOVATION_ID: [u8; 3] = [0x00, 0x20, 0x29];
+
+// Generator: ask the Launchpad X for its identity (the reply is handled by DeviceWatcher,
+// as RMididings doesn't receive SysEx).
+pub fn LPXDeviceInquiry<'a>() -> FilterChain<'a> {
+    Chain!(SysEx(&DEVICE_INQUIRY), LPX())
+}
+
+// Identity of a MIDI device, from its reply to a universal device inquiry.
+#[derive(Clone,Debug,PartialEq)]
+pub struct DeviceInfo {
+    // Manufacturer ID, one byte or three bytes starting with 0.
+    pub manufacturer: Vec<u8>,
+    pub family: [u8; 2],
+    pub model: [u8; 2],
+    pub version: [u8; 4],
+}
+
+impl DeviceInfo {
+    // Parse a device inquiry reply, None if it isn't one.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let body = data.strip_prefix(&[0xf0, 0x7e])?.strip_suffix(&[0xf7])?;
+        // Skip the device ID.
+        let body = body.get(1..)?.strip_prefix(&[0x06, 0x02])?;
+        let id_len = if body.first() == Some(&0x00) { 3 } else { 1 };
+        match *body {
+            [ref manufacturer @ .., f1, f2, m1, m2, v1, v2, v3, v4] if manufacturer.len() == id_len => Some(DeviceInfo {
+                manufacturer: manufacturer.to_vec(),
+                family: [f1, f2],
+                model: [m1, m2],
+                version: [v1, v2, v3, v4],
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn is_device(&self, profile: &dyn DeviceProfile) -> bool {
+        self.manufacturer == NOVATION_ID && self.family == profile.family()
+    }
+
+    // Firmware version, the Launchpads send it as four decimal digits.
+    pub fn firmware(&self) -> u32 {
+        self.version.iter().fold(0, |version, digit| version * 10 + *digit as u32)
+    }
+}
+
+// Modifier: move events from one channel to another
+pub struct ChannelMap(pub u8, pub u8);
+impl FilterTrait for ChannelMap {
+    fn run(&self, evs: &mut EventStream) {
+        for ev in evs.iter_mut() {
+            if ev.channel() == Some(self.0) {
+                ev.set_channel(self.1);
+            }
+        }
+    }
+}
+
+// Modifier: add an offset to controller numbers, like Transpose does for notes
+pub struct CtrlOffset(pub i32);
+impl FilterTrait for CtrlOffset {
+    fn run(&self, evs: &mut EventStream) {
+        for ev in evs.iter_mut() {
+            if let Event::Ctrl(ev) = ev {
+                ev.ctrl = (ev.ctrl as i32 + self.0) as u32;
+            }
+        }
+    }
+}
+
+// Grid of the Launchpad X, with x from the left and y from the bottom (0-8): the pads are 0-7, the
+// right column has x 8 and the top row y 8. The programmer layout numbers them 11-99 (from the row
+// and column, counting from 1), the session layout numbers its pad notes and the buttons the same.
+
+// Pad of the 8x8 grid, a note in the session and programmer layouts.
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub struct LpxPad {
+    pub x: u8,
+    pub y: u8,
+}
+
+impl LpxPad {
+    pub fn from_xy(x: u8, y: u8) -> Option<Self> {
+        if x < 8 && y < 8 { Some(LpxPad { x, y }) } else { None }
+    }
+
+    pub fn from_number(number: u8) -> Option<Self> {
+        Self::from_xy((number % 10).checked_sub(1)?, (number / 10).checked_sub(1)?)
+    }
+
+    // All pads, row by row from the bottom left.
+    pub fn all() -> impl Iterator<Item = LpxPad> {
+        (0..8).flat_map(|y| (0..8).map(move |x| LpxPad { x, y }))
+    }
+
+    // Number in the programmer layout, which is also the note in the session layout.
+    pub fn number(self) -> u8 {
+        (self.y + 1) * 10 + self.x + 1
+    }
+}
+
+// Buttons of the top row and the right column, named as on the Launchpad X, controllers in the session
+// and programmer layouts. Some have a different function on other devices (see DeviceButton).
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum LpxButton {
+    Up,
+    Down,
+    Left,
+    Right,
+    Session,
+    Note,
+    Custom,
+    CaptureMidi,
+    Logo,
+    Volume,
+    Pan,
+    SendA,
+    SendB,
+    StopClip,
+    Mute,
+    Solo,
+    RecordArm,
+}
+
+impl LpxButton {
+    // The top row from the left with the logo, then the right column from the top.
+    pub const ALL: [LpxButton; 17] = [
+        LpxButton::Up, LpxButton::Down, LpxButton::Left, LpxButton::Right,
+        LpxButton::Session, LpxButton::Note, LpxButton::Custom, LpxButton::CaptureMidi, LpxButton::Logo,
+        LpxButton::Volume, LpxButton::Pan, LpxButton::SendA, LpxButton::SendB,
+        LpxButton::StopClip, LpxButton::Mute, LpxButton::Solo, LpxButton::RecordArm,
+    ];
+
+    pub fn from_xy(x: u8, y: u8) -> Option<Self> {
+        Self::ALL.iter().copied().find(|button| button.xy() == (x, y))
+    }
+
+    pub fn from_number(number: u8) -> Option<Self> {
+        Self::from_xy((number % 10).checked_sub(1)?, (number / 10).checked_sub(1)?)
+    }
+
+    pub fn from_ctrl(ctrl: u32) -> Option<Self> {
+        if ctrl > 127 { return None; }
+        Self::from_number(ctrl as u8)
+    }
+
+    // Button in the right column, counted from the top (0-7).
+    pub fn scene(row: u8) -> Option<Self> {
+        Self::ALL.get(9 + row as usize).copied()
+    }
+
+    pub fn xy(self) -> (u8, u8) {
+        let i = Self::ALL.iter().position(|button| *button == self).unwrap() as u8;
+        if i < 9 { (i, 8) } else { (8, 16 - i) }
+    }
+
+    // Number in the programmer layout.
+    pub fn number(self) -> u8 {
+        let (x, y) = self.xy();
+        (y + 1) * 10 + x + 1
+    }
+
+    // Controller in the session layout.
+    pub fn ctrl(self) -> u32 {
+        self.number() as u32
+    }
+}
+
+impl From<LpxPad> for NoteOrCtrl {
+    fn from(pad: LpxPad) -> Self {
+        NoteOrCtrl::Note(pad.number())
+    }
+}
+
+impl From<LpxButton> for NoteOrCtrl {
+    fn from(button: LpxButton) -> Self {
+        NoteOrCtrl::Ctrl(button.ctrl())
+    }
+}
+
+// A plain number is a button controller, as DeviceProfile::button gives.
+impl From<u32> for NoteOrCtrl {
+    fn from(ctrl: u32) -> Self {
+        NoteOrCtrl::Ctrl(ctrl)
+    }
+}
+
+// Lighting mode of a button, which the Launchpad X selects by MIDI channel.
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum LPXMode {
+    // Static color, on channel 1.
+    Static,
+    // Flashing between the button color and this color, on channel 2.
+    Flashing(LPXColor),
+    // Pulsing button color, on channel 3.
+    Pulsing,
+}
+
+// Generator: set Launchpad X button (or pad) to a specific color
+pub fn LPXButton<'a>(button: impl Into<NoteOrCtrl>, color: LPXColor) -> FilterChain<'a> {
+    LPXButtonMode(button, color, LPXMode::Static)
+}
+
+// Generator: set Launchpad X button (or pad) to a specific color and lighting mode
+pub fn LPXButtonMode<'a>(button: impl Into<NoteOrCtrl>, color: LPXColor, mode: LPXMode) -> FilterChain<'a> {
+    let button = button.into();
+    let light = |color: LPXColor, channel| -> FilterChain<'a> {
+        match button {
+            NoteOrCtrl::Note(note) => Chain!(NoteOn(note, color as u8), Channel(channel), LPX()),
+            NoteOrCtrl::Ctrl(ctrl) => Chain!(Ctrl(ctrl, color as i32), Channel(channel), LPX()),
+        }
+    };
+    match mode {
+        LPXMode::Static => light(color, 1),
+        LPXMode::Flashing(other) => Fork!(light(color, 1), light(other, 2)),
+        LPXMode::Pulsing => light(color, 3),
+    }
+}
+
+// Lighting of a single LED, as used by LPXLighting.
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum LPXLight {
+    // Palette color.
+    Static(u8),
+    // Flashing between two palette colors (B and A).
+    Flashing(u8, u8),
+    // Pulsing palette color.
+    Pulsing(u8),
+    // Red, green and blue components (0-127).
+    Rgb(u8, u8, u8),
+}
+
+impl LPXLight {
+    // RGB lighting from 8-bit components, as commonly used by music applications.
+    pub fn from_rgb8(r: u8, g: u8, b: u8) -> Self {
+        LPXLight::Rgb(r >> 1, g >> 1, b >> 1)
+    }
+
+    // Append the colorspec for an LED to LED lighting SysEx data.
+    fn push_colorspec(&self, led: u8, data: &mut Vec<u8>) {
+        match *self {
+            LPXLight::Static(color) => data.extend(&[0x00, led, color & 0x7f]),
+            LPXLight::Flashing(b, a) => data.extend(&[0x01, led, b & 0x7f, a & 0x7f]),
+            LPXLight::Pulsing(color) => data.extend(&[0x02, led, color & 0x7f]),
+            LPXLight::Rgb(r, g, b) => data.extend(&[0x03, led, r & 0x7f, g & 0x7f, b & 0x7f]),
+        }
+    }
+
+    // Parse the colorspec at the start of LED lighting SysEx data, returning the rest.
+    fn parse_colorspec(data: &[u8]) -> Option<((u8, Self), &[u8])> {
+        match *data {
+            [0x00, led, color, ref rest @ ..] => Some(((led, LPXLight::Static(color)), rest)),
+            [0x01, led, b, a, ref rest @ ..] => Some(((led, LPXLight::Flashing(b, a)), rest)),
+            [0x02, led, color, ref rest @ ..] => Some(((led, LPXLight::Pulsing(color)), rest)),
+            [0x03, led, r, g, b, ref rest @ ..] => Some(((led, LPXLight::Rgb(r, g, b)), rest)),
+            _ => None,
+        }
+    }
+}
+
+// Generator: light any number of buttons and pads (11-99) in a single LED lighting SysEx.
+pub fn LPXLighting<'a>(profile: &dyn DeviceProfile, leds: &[(u32, LPXLight)]) -> FilterChain<'a> {
+    LPXSysEx(profile, LpxSysEx::Lighting(leds.iter().map(|(led, light)| (*led as u8, *light)).collect()))
+}
+
+// Filter: pass events from a specific button (or pad) press on the Launchpad X
+pub fn LPXButtonFilter<'a>(button: impl Into<NoteOrCtrl>) -> FilterChain<'a> {
+    Chain!(LPXFilter(), LPXPressFilter(button.into()))
+}
+
+// Filter: pass presses (not releases) of a button or pad on channel 1
+pub struct LPXPressFilter(pub NoteOrCtrl);
+impl FilterTrait for LPXPressFilter {
+    fn run(&self, evs: &mut EventStream) {
+        evs.retain(|ev| NoteOrCtrl::find(&[self.0], 1, ev) == Some((0, true)));
+    }
+}
+
+// Launchpad X color palette, with the RGB values of Novation's velocity palette.
+// Names of the first 64 entries follow the palette layout (light, full, dark and dim of each hue), the
+// others are named after their color. Some of these repeat an earlier color (e.g. Brightred is Red),
+// for which LPXColor::nearest gives the earlier one.
+macro_rules! lpx_palette { ($($name:ident = $index:expr, ($r:expr, $g:expr, $b:expr);)*) => {
+    #[allow(dead_code)]
+    #[derive(Copy,Clone,Debug,PartialEq)]
+    pub enum LPXColor {
+        $($name = $index,)*
+    }
+
+    // All palette colors with their RGB values, in order of palette index.
+    pub const LPX_PALETTE: [(LPXColor, (u8, u8, u8)); 128] = [
+        $((LPXColor::$name, ($r, $g, $b)),)*
+    ];
 } }
 
-#[allow(dead_code)]
-#[derive(Copy,Clone)]
-pub enum LPXColor {
-    Black = 0,
-    Softwhite = 1,
-    White = 3,
-    Red = 5,
-    Orange = 9,
-    Brown = 11,
-    Yellow = 13,
-    Green = 21,
-    Softgreen = 19, // TODO find right shade of green
-    Blue = 45,
+lpx_palette! {
+    Black = 0, (0, 0, 0);
+    Softwhite = 1, (30, 30, 30);
+    Grey = 2, (127, 127, 127);
+    White = 3, (255, 255, 255);
+    Lightred = 4, (255, 76, 76);
+    Red = 5, (255, 0, 0);
+    Darkred = 6, (89, 0, 0);
+    Dimred = 7, (25, 0, 0);
+    Lightorange = 8, (255, 189, 108);
+    Orange = 9, (255, 84, 0);
+    Darkorange = 10, (89, 29, 0);
+    Brown = 11, (39, 27, 0);
+    Lightyellow = 12, (255, 255, 76);
+    Yellow = 13, (255, 255, 0);
+    Darkyellow = 14, (89, 89, 0);
+    Dimyellow = 15, (25, 25, 0);
+    Lightlime = 16, (136, 255, 76);
+    Lime = 17, (84, 255, 0);
+    Darklime = 18, (29, 89, 0);
+    Softgreen = 19, (20, 43, 0);
+    Lightgreen = 20, (76, 255, 76);
+    Green = 21, (0, 255, 0);
+    Darkgreen = 22, (0, 89, 0);
+    Dimgreen = 23, (0, 25, 0);
+    Lightemerald = 24, (76, 255, 94);
+    Emerald = 25, (0, 255, 25);
+    Darkemerald = 26, (0, 89, 13);
+    Dimemerald = 27, (0, 25, 2);
+    Lightmint = 28, (76, 255, 136);
+    Mint = 29, (0, 255, 85);
+    Darkmint = 30, (0, 89, 29);
+    Dimmint = 31, (0, 31, 18);
+    Lightturquoise = 32, (76, 255, 183);
+    Turquoise = 33, (0, 255, 153);
+    Darkturquoise = 34, (0, 89, 53);
+    Dimturquoise = 35, (0, 25, 18);
+    Lightsky = 36, (76, 195, 255);
+    Sky = 37, (0, 169, 255);
+    Darksky = 38, (0, 65, 82);
+    Dimsky = 39, (0, 16, 25);
+    Lightazure = 40, (76, 136, 255);
+    Azure = 41, (0, 85, 255);
+    Darkazure = 42, (0, 29, 89);
+    Dimazure = 43, (0, 8, 25);
+    Lightblue = 44, (76, 76, 255);
+    Blue = 45, (0, 0, 255);
+    Darkblue = 46, (0, 0, 89);
+    Dimblue = 47, (0, 0, 25);
+    Lightviolet = 48, (135, 76, 255);
+    Violet = 49, (84, 0, 255);
+    Darkviolet = 50, (25, 0, 100);
+    Dimviolet = 51, (15, 0, 48);
+    Lightmagenta = 52, (255, 76, 255);
+    Magenta = 53, (255, 0, 255);
+    Darkmagenta = 54, (89, 0, 89);
+    Dimmagenta = 55, (25, 0, 25);
+    Lightpink = 56, (255, 76, 135);
+    Pink = 57, (255, 0, 84);
+    Darkpink = 58, (89, 0, 29);
+    Dimpink = 59, (34, 0, 19);
+    Scarlet = 60, (255, 21, 0);
+    Rust = 61, (153, 53, 0);
+    Olive = 62, (121, 81, 0);
+    Moss = 63, (67, 100, 0);
+    Forest = 64, (3, 57, 0);
+    Pine = 65, (0, 87, 53);
+    Steel = 66, (0, 84, 127);
+    Royalblue = 67, (0, 0, 255);
+    Slate = 68, (0, 69, 79);
+    Indigo = 69, (37, 0, 204);
+    Midgrey = 70, (127, 127, 127);
+    Charcoal = 71, (32, 32, 32);
+    Brightred = 72, (255, 0, 0);
+    Chartreuse = 73, (189, 255, 45);
+    Yellowgreen = 74, (175, 237, 6);
+    Brightlime = 75, (100, 255, 9);
+    Grassgreen = 76, (16, 139, 0);
+    Aquagreen = 77, (0, 255, 135);
+    Cerulean = 78, (0, 169, 255);
+    Brightblue = 79, (0, 42, 255);
+    Ultramarine = 80, (63, 0, 255);
+    Purple = 81, (122, 0, 255);
+    Plum = 82, (178, 26, 125);
+    Chocolate = 83, (64, 33, 0);
+    Tangerine = 84, (255, 74, 0);
+    Pear = 85, (136, 225, 6);
+    Brightgreen = 86, (114, 255, 21);
+    Neongreen = 87, (0, 255, 0);
+    Electricgreen = 88, (59, 255, 38);
+    Palegreen = 89, (89, 255, 113);
+    Aquamarine = 90, (56, 255, 204);
+    Cornflower = 91, (91, 138, 255);
+    Cobalt = 92, (49, 81, 198);
+    Lavender = 93, (135, 127, 233);
+    Orchid = 94, (211, 29, 255);
+    Rose = 95, (255, 0, 93);
+    Amber = 96, (255, 127, 0);
+    Mustard = 97, (185, 176, 0);
+    Lawngreen = 98, (144, 255, 0);
+    Ochre = 99, (131, 93, 7);
+    Darkbrown = 100, (57, 43, 0);
+    Huntergreen = 101, (20, 76, 16);
+    Darkteal = 102, (13, 80, 56);
+    Midnight = 103, (21, 21, 42);
+    Navy = 104, (22, 32, 90);
+    Sienna = 105, (105, 60, 28);
+    Crimson = 106, (168, 0, 10);
+    Coral = 107, (222, 81, 61);
+    Pumpkin = 108, (216, 106, 28);
+    Gold = 109, (255, 225, 38);
+    Applegreen = 110, (158, 225, 47);
+    Leafgreen = 111, (103, 181, 15);
+    Darkslate = 112, (30, 30, 48);
+    Lemon = 113, (220, 255, 107);
+    Seafoam = 114, (128, 255, 189);
+    Periwinkle = 115, (154, 153, 255);
+    Amethyst = 116, (142, 102, 255);
+    Darkgrey = 117, (64, 64, 64);
+    Silver = 118, (117, 117, 117);
+    Ice = 119, (224, 255, 255);
+    Brick = 120, (160, 0, 0);
+    Oxblood = 121, (53, 0, 0);
+    Kellygreen = 122, (26, 208, 0);
+    Darkforest = 123, (7, 66, 0);
+    Khaki = 124, (185, 176, 0);
+    Umber = 125, (63, 49, 0);
+    Copper = 126, (179, 95, 0);
+    Mahogany = 127, (75, 21, 2);
+}
+
+impl LPXColor {
+    // Color at a palette index (0-127).
+    pub fn from_index(index: u8) -> Self {
+        LPX_PALETTE[(index & 0x7f) as usize].0
+    }
+
+    // Approximate RGB value of this color.
+    #[allow(dead_code)]
+    pub fn rgb(self) -> (u8, u8, u8) {
+        LPX_PALETTE[self as usize].1
+    }
+
+    // Palette color that is closest to an RGB value.
+    pub fn nearest(r: u8, g: u8, b: u8) -> Self {
+        let distance = |(pr, pg, pb): (u8, u8, u8)| {
+            // Weighted for the eye's sensitivity to green, then red, then blue.
+            let (dr, dg, db) = (pr as i32 - r as i32, pg as i32 - g as i32, pb as i32 - b as i32);
+            3 * dr * dr + 4 * dg * dg + 2 * db * db
+        };
+        LPX_PALETTE.iter().min_by_key(|(_, rgb)| distance(*rgb)).unwrap().0
+    }
+}
+
+// Parse a color name (case-insensitive) or an RGB value like "#ff8000" (giving the nearest color).
+impl FromStr for LPXColor {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with('#') {
+            let (r, g, b) = parse_rgb(s).ok_or(())?;
+            return Ok(LPXColor::nearest(r, g, b));
+        }
+        LPX_PALETTE.iter()
+            .map(|(color, _)| *color)
+            .find(|color| format!("{:?}", color).eq_ignore_ascii_case(s))
+            .ok_or(())
+    }
+}
+
+// Parse a color name as a static palette color, or an RGB value like "#ff8000" as that exact color.
+impl FromStr for LPXLight {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match parse_rgb(s) {
+            Some((r, g, b)) => Ok(LPXLight::from_rgb8(r, g, b)),
+            None => s.parse::<LPXColor>().map(|color| LPXLight::Static(color as u8)),
+        }
+    }
+}
+
+// RGB value like "#ff8000".
+fn parse_rgb(s: &str) -> Option<(u8, u8, u8)> {
+    let hex = s.strip_prefix('#')?;
+    if hex.len() != 6 { return None; }
+    let rgb = u32::from_str_radix(hex, 16).ok()?;
+    Some(((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
 }
 
 // Controller memory.
+// Values on channel 2 and 3 are remembered as the flashing or pulsing mode of the controller.
 // TODO move this to RMididings
 pub struct CtrlsMemory<'a> {
     ctrls: &'a [u32],
     values: Vec<Cell<Option<i32>>>,
+    modes: Vec<Cell<Option<(u8, i32)>>>,
 }
 
 impl<'a> CtrlsMemory<'a> {
     pub fn new(ctrls: &'a [u32], initial_value: Option<i32>) -> Self {
         let values = vec![Cell::new(initial_value); ctrls.len()];
-        CtrlsMemory { ctrls, values }
+        let modes = vec![Cell::new(None); ctrls.len()];
+        CtrlsMemory { ctrls, values, modes }
     }
 
     // Return a filter that stores any of the indicated controller values.
-    pub fn Store(&self) -> CtrlsMemoryStore {
-        CtrlsMemoryStore(&self.ctrls, &self.values)
+    pub fn Store(&self) -> CtrlsMemoryStore<'_> {
+        CtrlsMemoryStore(self.ctrls, &self.values, &self.modes)
     }
 
     // Return a generator that emits any stored controller values.
-    pub fn Restore(&self) -> CtrlsMemoryRestore {
-        CtrlsMemoryRestore(&self.ctrls, &self.values)
+    pub fn Restore(&self) -> CtrlsMemoryRestore<'_> {
+        CtrlsMemoryRestore(self.ctrls, &self.values, &self.modes)
     }
 
-    // Directory set the memory for a specific controller value.
+    // Directly set the memory for a specific controller value.
     pub fn store(&self, ctrl: u32, value: Option<i32>) {
         if let Some(index) = self.ctrls.iter().position(|&c| c == ctrl) {
             if let Some(v) = self.values.get(index) {
                 v.set(value);
+                self.modes[index].set(None);
             }
         }
     }
 
     // Directly set the memory for all controller values.
     pub fn store_all(&self, value: Option<i32>) {
-        for v in self.values.iter() {
+        for (v, m) in self.values.iter().zip(self.modes.iter()) {
             v.set(value);
+            m.set(None);
         }
     }
 }
 
-pub struct CtrlsMemoryStore<'a>(&'a [u32], &'a Vec<Cell<Option<i32>>>);
+pub struct CtrlsMemoryStore<'a>(&'a [u32], &'a Vec<Cell<Option<i32>>>, &'a Vec<Cell<Option<(u8, i32)>>>);
 impl<'a> FilterTrait for CtrlsMemoryStore<'a> {
     fn run(&self, evs: &mut EventStream) {
         for ev in evs.iter() {
-            match ev {
-                Event::Ctrl(ev) => {
-                    if let Some(i) = self.0.iter().position(|&c| c == ev.ctrl) {
-                        self.1[i].set(Some(ev.value));
+            if let Event::Ctrl(ev) = ev {
+                if let Some(i) = self.0.iter().position(|&c| c == ev.ctrl) {
+                    match ev.channel {
+                        // Flashing or pulsing, on top of the static value.
+                        2 | 3 => self.2[i].set(Some((ev.channel, ev.value))),
+                        // A static value stops flashing or pulsing.
+                        _ => {
+                            self.1[i].set(Some(ev.value));
+                            self.2[i].set(None);
+                        },
                     }
-                },
-                _ => {},
+                }
             }
         }
     }
 }
 
-pub struct CtrlsMemoryRestore<'a>(&'a [u32], &'a Vec<Cell<Option<i32>>>);
+pub struct CtrlsMemoryRestore<'a>(&'a [u32], &'a Vec<Cell<Option<i32>>>, &'a Vec<Cell<Option<(u8, i32)>>>);
 impl<'a> FilterTrait for CtrlsMemoryRestore<'a> {
     fn run(&self, evs: &mut EventStream) {
+        if evs.is_empty() { return; }
+        evs.clear();
         for i in 0..self.0.len() {
+            let ctrl = self.0[i];
             if let Some(value) = self.1[i].get() {
-                let ctrl = self.0[i];
-                evs.push(CtrlEvent(0, 0, ctrl, value));
+                evs.push(CtrlEvent(0, 1, ctrl, value));
+            }
+            if let Some((channel, value)) = self.2[i].get() {
+                evs.push(CtrlEvent(0, channel, ctrl, value));
+            }
+        }
+    }
+}
+
+// Note memory, like CtrlsMemory but for pads, which are notes (e.g. the session layout grid).
+// Notes on channel 2 and 3 are remembered as the flashing or pulsing mode of the pad.
+pub struct NotesMemory<'a> {
+    notes: &'a [u8],
+    values: Vec<Cell<Option<u8>>>,
+    modes: Vec<Cell<Option<(u8, u8)>>>,
+}
+
+impl<'a> NotesMemory<'a> {
+    pub fn new(notes: &'a [u8], initial_value: Option<u8>) -> Self {
+        let values = vec![Cell::new(initial_value); notes.len()];
+        let modes = vec![Cell::new(None); notes.len()];
+        NotesMemory { notes, values, modes }
+    }
+
+    // Return a filter that stores the color of any of the indicated notes.
+    pub fn Store(&self) -> NotesMemoryStore<'_> {
+        NotesMemoryStore(self.notes, &self.values, &self.modes)
+    }
+
+    // Return a generator that emits any stored note colors.
+    pub fn Restore(&self) -> NotesMemoryRestore<'_> {
+        NotesMemoryRestore(self.notes, &self.values, &self.modes)
+    }
+}
+
+pub struct NotesMemoryStore<'a>(&'a [u8], &'a Vec<Cell<Option<u8>>>, &'a Vec<Cell<Option<(u8, u8)>>>);
+impl<'a> FilterTrait for NotesMemoryStore<'a> {
+    fn run(&self, evs: &mut EventStream) {
+        for ev in evs.iter() {
+            let (channel, note, velocity) = match ev {
+                Event::NoteOn(ev) => (ev.channel, ev.note, ev.velocity),
+                Event::NoteOff(ev) => (ev.channel, ev.note, 0),
+                _ => continue,
+            };
+            if let Some(i) = self.0.iter().position(|&n| n == note) {
+                match channel {
+                    // Flashing or pulsing, on top of the static color.
+                    2 | 3 => self.2[i].set(Some((channel, velocity))),
+                    // A static color stops flashing or pulsing.
+                    _ => {
+                        self.1[i].set(Some(velocity));
+                        self.2[i].set(None);
+                    },
+                }
+            }
+        }
+    }
+}
+
+pub struct NotesMemoryRestore<'a>(&'a [u8], &'a Vec<Cell<Option<u8>>>, &'a Vec<Cell<Option<(u8, u8)>>>);
+impl<'a> FilterTrait for NotesMemoryRestore<'a> {
+    fn run(&self, evs: &mut EventStream) {
+        if evs.is_empty() { return; }
+        evs.clear();
+        for i in 0..self.0.len() {
+            let note = self.0[i];
+            if let Some(velocity) = self.1[i].get() {
+                evs.push(NoteOnEvent(0, 1, note, velocity));
+            }
+            if let Some((channel, velocity)) = self.2[i].get() {
+                evs.push(NoteOnEvent(0, channel, note, velocity));
+            }
+        }
+    }
+}
+
+// Pads of the 8x8 grid, which are notes in the session layout (and in DAW mode), from the bottom left.
+pub const LPX_PADS: [u8; 64] = [
+    11, 12, 13, 14, 15, 16, 17, 18, 21, 22, 23, 24, 25, 26, 27, 28,
+    31, 32, 33, 34, 35, 36, 37, 38, 41, 42, 43, 44, 45, 46, 47, 48,
+    51, 52, 53, 54, 55, 56, 57, 58, 61, 62, 63, 64, 65, 66, 67, 68,
+    71, 72, 73, 74, 75, 76, 77, 78, 81, 82, 83, 84, 85, 86, 87, 88,
+];
+
+// Buttons of the right column and the top row (with the logo), which are controllers.
+pub const LPX_BUTTONS: [u32; 17] = [19, 29, 39, 49, 59, 69, 79, 89, 91, 92, 93, 94, 95, 96, 97, 98, 99];
+
+// LED memory of the whole Launchpad X surface (81 LEDs), like CtrlsMemory for the buttons and
+// NotesMemory for the pads. Only LED colors (channels 1-3) are stored.
+pub struct LedMemory {
+    pads: NotesMemory<'static>,
+    buttons: CtrlsMemory<'static>,
+}
+
+impl LedMemory {
+    pub fn new(initial_pad: Option<u8>) -> Self {
+        LedMemory { pads: NotesMemory::new(&LPX_PADS, initial_pad), buttons: CtrlsMemory::new(&LPX_BUTTONS, None) }
+    }
+
+    // Return a filter that stores the colors of pads and buttons, and passes only these.
+    pub fn Store(&self) -> FilterChain<'_> {
+        Chain!(ChannelsFilter(&[1, 2, 3]), self.pads.Store(), self.buttons.Store())
+    }
+
+    // Return a generator that emits all stored colors.
+    pub fn Restore(&self) -> FilterChain<'_> {
+        Fork!(self.pads.Restore(), self.buttons.Restore())
+    }
+
+    // Directly set the memory for a button.
+    pub fn store_button(&self, button: u32, value: Option<i32>) {
+        self.buttons.store(button, value);
+    }
+}
+
+// What an event does to the LEDs, see LedFramebuffer.
+#[derive(Clone,Debug,PartialEq)]
+enum LedChange {
+    // Not about LEDs.
+    None,
+    // Lights these LEDs.
+    Light(Vec<u8>),
+    // Makes the device redraw LEDs itself (e.g. selecting a layout), so we don't know them anymore.
+    Forget,
+}
+
+// Number of lighting SysEx messages that the LED framebuffer keeps, after which the LEDs that change
+// are sent with a message each (except for RGB colors, which are only in the configuration). SysEx
+// events need static data, so these are kept until we stop: at most about 420 bytes each (all LEDs
+// with an RGB color), which is about 100 kB per device.
+const LIGHTING_MESSAGES: usize = 256;
+
+// LED framebuffer: the lighting the device currently shows (as far as we know). Flush turns LED
+// messages into a single LED lighting SysEx with only the LEDs that change (or a message for the LED
+// when only one changes), while Track follows messages that are sent to the device otherwise, so that
+// everything sent to it must pass one of these.
+// Messages from the Controller can be rate limited with Limit, which holds back updates of an LED that
+// was updated less than a window ago, or when too many were sent in the window. The latest of these
+// is sent on the next Tick (see LedTicker), unless a Flush lit the LED in the meantime.
+pub struct LedFramebuffer {
+    profile: &'static dyn DeviceProfile,
+    // Lighting of LEDs 11-99, None when unknown.
+    leds: Vec<Cell<Option<LPXLight>>>,
+    // Lighting SysEx messages sent before, SysEx events need static data (see LIGHTING_MESSAGES).
+    messages: RefCell<HashSet<&'static [u8]>>,
+    window: Duration,
+    // Number of messages that may be sent in a window.
+    burst: usize,
+    // Start of the current window, and the number of messages sent in it.
+    sent: Cell<Option<(Instant, usize)>>,
+    // Last update of each LED and channel.
+    updated: RefCell<HashMap<(u8, u8), Instant>>,
+    // Updates that are held back, only the last for each LED and channel, in order.
+    pending: RefCell<Vec<((u8, u8), Event<'static>)>>,
+    // Whether there are pending updates, for the thread that ticks.
+    ticking: Arc<AtomicBool>,
+}
+
+impl LedFramebuffer {
+    // Rate limited to messages per second, with at most one update of an LED in a window.
+    pub fn new(profile: &'static dyn DeviceProfile, window: Duration, rate: u32) -> Self {
+        LedFramebuffer {
+            profile,
+            leds: vec![Cell::new(None); 89],
+            messages: RefCell::new(HashSet::new()),
+            window,
+            burst: ((rate as u128 * window.as_millis() / 1000) as usize).max(1),
+            sent: Cell::new(None),
+            updated: RefCell::new(HashMap::new()),
+            pending: RefCell::new(vec![]),
+            ticking: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    // Return a filter that replaces LED messages by a lighting SysEx with only the changed LEDs.
+    // Other events are passed (before the lighting), so use it on events for the device only.
+    pub fn Flush(&self) -> LedFramebufferFlush<'_> {
+        LedFramebufferFlush(self)
+    }
+
+    // Return a filter that follows the LEDs in events for the Launchpad X port, passing all events.
+    pub fn Track(&self) -> LedFramebufferTrack<'_> {
+        LedFramebufferTrack(self)
+    }
+
+    // Return a filter that holds back LED messages that come too soon or too many, passing other events.
+    pub fn Limit(&self) -> LedFramebufferLimit<'_> {
+        LedFramebufferLimit(self)
+    }
+
+    // Return a generator that sends the LED messages that were held back, as far as the rate allows.
+    pub fn Tick(&self) -> LedFramebufferTick<'_> {
+        LedFramebufferTick(self)
+    }
+
+    // Set while there are messages held back, so that Tick needs to be run.
+    pub fn ticking(&self) -> Arc<AtomicBool> {
+        self.ticking.clone()
+    }
+
+    fn led(leds: &[Cell<Option<LPXLight>>], led: u8) -> Option<&Cell<Option<LPXLight>>> {
+        leds.get((led as usize).checked_sub(11)?)
+    }
+
+    // LED and channel of an LED message, with a copy of it.
+    fn message_led(ev: &Event) -> Option<((u8, u8), Event<'static>)> {
+        let (led, channel, ev) = match ev {
+            Event::NoteOn(ev) if LPX_PADS.contains(&ev.note) => (ev.note, ev.channel, NoteOnEvent(ev.port, ev.channel, ev.note, ev.velocity)),
+            Event::NoteOff(ev) if LPX_PADS.contains(&ev.note) => (ev.note, ev.channel, NoteOffEvent(ev.port, ev.channel, ev.note)),
+            Event::Ctrl(ev) if LPX_BUTTONS.contains(&ev.ctrl) => (ev.ctrl as u8, ev.channel, CtrlEvent(ev.port, ev.channel, ev.ctrl, ev.value)),
+            _ => return None,
+        };
+        if (1..=3).contains(&channel) { Some(((led, channel), ev)) } else { None }
+    }
+
+    // Light an LED with a message on a channel (1 static, 2 flashing, 3 pulsing).
+    fn light(leds: &[Cell<Option<LPXLight>>], led: u8, channel: u8, value: u8) -> LedChange {
+        let cell = match Self::led(leds, led) { Some(cell) => cell, None => return LedChange::None };
+        let light = match channel {
+            1 => LPXLight::Static(value),
+            // Flashing between the static color and this one.
+            2 => match cell.get() {
+                Some(LPXLight::Static(color)) | Some(LPXLight::Flashing(color, _)) | Some(LPXLight::Pulsing(color)) =>
+                    LPXLight::Flashing(color, value),
+                _ => LPXLight::Flashing(0, value),
+            },
+            3 => LPXLight::Pulsing(value),
+            _ => return LedChange::None,
+        };
+        cell.set(Some(light));
+        LedChange::Light(vec![led])
+    }
+
+    // Forget LEDs, e.g. because the device draws them itself.
+    fn forget(leds: &[Cell<Option<LPXLight>>], forget: &[u8]) -> LedChange {
+        forget.iter().filter_map(|led| Self::led(leds, *led)).for_each(|cell| cell.set(None));
+        LedChange::Forget
+    }
+
+    // Apply an event to the lighting of LEDs.
+    fn apply(&self, leds: &[Cell<Option<LPXLight>>], ev: &Event) -> LedChange {
+        match ev {
+            Event::NoteOn(ev) if LPX_PADS.contains(&ev.note) => Self::light(leds, ev.note, ev.channel, ev.velocity),
+            Event::NoteOff(ev) if LPX_PADS.contains(&ev.note) => Self::light(leds, ev.note, ev.channel, 0),
+            Event::Ctrl(ev) if LPX_BUTTONS.contains(&ev.ctrl) => Self::light(leds, ev.ctrl as u8, ev.channel, ev.value as u8),
+            Event::SysEx(ev) => match LpxSysEx::parse_device(self.profile, ev.data) {
+                Some(LpxSysEx::Lighting(lights)) => {
+                    for (led, light) in lights.iter() {
+                        if let Some(cell) = Self::led(leds, *led) { cell.set(Some(*light)); }
+                    }
+                    LedChange::Light(lights.iter().map(|(led, _)| *led).collect())
+                },
+                Some(LpxSysEx::SelectLayout(_)) => Self::forget(leds, &LPX_PADS),
+                Some(LpxSysEx::DawMode(_)) | Some(LpxSysEx::ProgrammerMode(_)) => {
+                    Self::forget(leds, &LPX_PADS);
+                    Self::forget(leds, &LPX_BUTTONS.iter().map(|button| *button as u8).collect::<Vec<_>>())
+                },
+                _ => LedChange::None,
+            },
+            _ => LedChange::None,
+        }
+    }
+
+    // Lighting SysEx message, which is kept for the whole run (but sent again when it's the same),
+    // None when there are too many already, unless it's needed anyway.
+    fn message(&self, lights: Vec<(u8, LPXLight)>, needed: bool) -> Option<&'static [u8]> {
+        let data = LpxSysEx::Lighting(lights).to_device_bytes(self.profile);
+        let mut messages = self.messages.borrow_mut();
+        if let Some(message) = messages.get(&data[..]) {
+            return Some(message);
+        }
+        if messages.len() >= LIGHTING_MESSAGES && !needed { return None; }
+        let message: &'static [u8] = Box::leak(data.into_boxed_slice());
+        messages.insert(message);
+        Some(message)
+    }
+
+    // Messages that light each LED (channel 1 static, 2 flashing, 3 pulsing), RGB colors need a lighting SysEx.
+    fn led_messages(&self, lights: &[(u8, LPXLight)]) -> Vec<Event<'static>> {
+        let mut evs = vec![];
+        for (led, light) in lights.iter() {
+            let message = |channel, value| match LPX_PADS.contains(led) {
+                true => NoteOnEvent(0, channel, *led, value),
+                false => CtrlEvent(0, channel, *led as u32, value as i32),
+            };
+            match *light {
+                LPXLight::Static(color) => evs.push(message(1, color)),
+                LPXLight::Flashing(color, flash) => evs.extend(vec![message(1, color), message(2, flash)]),
+                LPXLight::Pulsing(color) => evs.push(message(3, color)),
+                LPXLight::Rgb(..) => evs.extend(self.message(vec![(*led, *light)], true).map(|message| SysExEvent(0, message))),
             }
         }
+        evs
+    }
+
+    // Return the events for LEDs (11-99) that don't show the lighting yet, if any.
+    pub fn set_lights(&self, lights: &[(u8, LPXLight)]) -> Vec<Event<'static>> {
+        let desired = self.leds.clone();
+        for (led, light) in lights.iter() {
+            if let Some(cell) = Self::led(&desired, *led) { cell.set(Some(*light)); }
+        }
+        self.update(&desired, &lights.iter().map(|(led, _)| *led).collect::<Vec<_>>())
+    }
+
+    // Make the desired lighting current, returning the events for the LEDs that change: a lighting SysEx
+    // when there's more than one (e.g. restoring a scene), otherwise a message for the LED.
+    fn update(&self, desired: &[Cell<Option<LPXLight>>], lit: &[u8]) -> Vec<Event<'static>> {
+        let mut lights = vec![];
+        for (i, (current, desired)) in self.leds.iter().zip(desired.iter()).enumerate() {
+            if let Some(light) = desired.get() {
+                if current.get() != Some(light) {
+                    lights.push((i as u8 + 11, light));
+                }
+            }
+            current.set(desired.get());
+        }
+        // Updates that were held back are older than these.
+        self.pending.borrow_mut().retain(|((led, _), _)| !lit.contains(led));
+        if lights.len() > 1 {
+            if let Some(message) = self.message(lights.clone(), false) { return vec![SysExEvent(0, message)]; }
+        }
+        self.led_messages(&lights)
+    }
+
+    // Take a message from the rate for the current window, false when there is none left.
+    fn take(&self, now: Instant) -> bool {
+        let (start, count) = match self.sent.get() {
+            Some((start, count)) if now.duration_since(start) < self.window => (start, count),
+            _ => (now, 0),
+        };
+        if count >= self.burst { return false; }
+        self.sent.set(Some((start, count + 1)));
+        true
+    }
+
+    // Whether an LED and channel were updated less than a window ago.
+    fn recent(&self, now: Instant, key: (u8, u8)) -> bool {
+        matches!(self.updated.borrow().get(&key), Some(updated) if now.duration_since(*updated) < self.window)
+    }
+
+    // Hold back an update, replacing an earlier one of the same LED and channel.
+    fn hold(&self, key: (u8, u8), ev: Event<'static>) {
+        let mut pending = self.pending.borrow_mut();
+        pending.retain(|(k, _)| *k != key);
+        pending.push((key, ev));
+        self.ticking.store(true, Ordering::Relaxed);
+    }
+
+    // Hold back LED messages that come too soon or too many at a time, see Limit.
+    fn limit(&self, evs: &mut EventStream, now: Instant) {
+        evs.retain(|ev| {
+            let (key, ev) = match LedFramebuffer::message_led(ev) { Some(led) => led, None => return true };
+            // Keep the order of updates of an LED (e.g. flashing on top of a static color).
+            let waiting = self.pending.borrow().iter().any(|((led, _), _)| *led == key.0);
+            if waiting || self.recent(now, key) || !self.take(now) {
+                self.hold(key, ev);
+                return false;
+            }
+            self.updated.borrow_mut().insert(key, now);
+            true
+        });
+    }
+
+    // Replace events by the LED messages that were held back and can be sent at a time, see Tick.
+    fn tick(&self, evs: &mut EventStream, now: Instant) {
+        if evs.is_empty() { return; }
+        evs.clear();
+        let mut waiting = vec![];
+        self.pending.borrow_mut().retain(|(key, ev)| {
+            if waiting.contains(&key.0) || self.recent(now, *key) || !self.take(now) {
+                waiting.push(key.0);
+                return true;
+            }
+            self.updated.borrow_mut().insert(*key, now);
+            evs.push(ev.clone());
+            false
+        });
+        self.ticking.store(!self.pending.borrow().is_empty(), Ordering::Relaxed);
+    }
+}
+
+pub struct LedFramebufferFlush<'a>(&'a LedFramebuffer);
+impl<'a> FilterTrait for LedFramebufferFlush<'a> {
+    fn run(&self, evs: &mut EventStream) {
+        let desired = self.0.leds.clone();
+        let mut lit = vec![];
+        evs.retain(|ev| match self.0.apply(&desired, ev) {
+            LedChange::Light(leds) => { lit.extend(leds); false },
+            // The device doesn't show what it did before, so all these LEDs change.
+            LedChange::Forget => { self.0.apply(&self.0.leds, ev); true },
+            LedChange::None => true,
+        });
+        evs.extend(self.0.update(&desired, &lit));
+    }
+}
+
+pub struct LedFramebufferTrack<'a>(&'a LedFramebuffer);
+impl<'a> FilterTrait for LedFramebufferTrack<'a> {
+    fn run(&self, evs: &mut EventStream) {
+        for ev in evs.iter().filter(|ev| ev.port() == Some(LPX().0)) {
+            self.0.apply(&self.0.leds, ev);
+        }
+    }
+}
+
+pub struct LedFramebufferLimit<'a>(&'a LedFramebuffer);
+impl<'a> FilterTrait for LedFramebufferLimit<'a> {
+    fn run(&self, evs: &mut EventStream) {
+        self.0.limit(evs, Instant::now());
+    }
+}
+
+pub struct LedFramebufferTick<'a>(&'a LedFramebuffer);
+impl<'a> FilterTrait for LedFramebufferTick<'a> {
+    fn run(&self, evs: &mut EventStream) {
+        self.0.tick(evs, Instant::now());
+    }
+}
+
+// A note or controller number, for messages that can be either (see ToggleMemory).
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum NoteOrCtrl {
+    Note(u8),
+    Ctrl(u32),
+}
+
+impl NoteOrCtrl {
+    // Event with a value (as note velocity or controller value).
+    pub fn event<'a>(self, channel: u8, value: u8) -> Event<'a> {
+        match self {
+            NoteOrCtrl::Note(note) => NoteOnEvent(0, channel, note, value),
+            NoteOrCtrl::Ctrl(ctrl) => CtrlEvent(0, channel, ctrl, value as i32),
+        }
+    }
+
+    // Index in numbers of an event on channel, and whether it is on (pressed).
+    pub fn find(numbers: &[NoteOrCtrl], channel: u8, ev: &Event) -> Option<(usize, bool)> {
+        let (number, on) = match ev {
+            Event::NoteOn(ev) if ev.channel == channel => (NoteOrCtrl::Note(ev.note), ev.velocity > 0),
+            Event::NoteOff(ev) if ev.channel == channel => (NoteOrCtrl::Note(ev.note), false),
+            Event::Ctrl(ev) if ev.channel == channel => (NoteOrCtrl::Ctrl(ev.ctrl), ev.value > 0),
+            _ => return None,
+        };
+        numbers.iter().position(|n| *n == number).map(|i| (i, on))
+    }
+}
+
+// Toggle memory, which latches momentary presses of Launchpad X pads or buttons into on/off states.
+// Each toggle sends its state to the controller (127 or 0) on a note or controller, and the controller
+// can set it by sending it back. The LED shows the state with a color for on and one for off.
+// Momentary toggles send 127 on every press instead, and only show the state the controller sends.
+pub struct ToggleMemory {
+    // Pads (notes) or buttons (controllers) on the Launchpad X, on channel 1.
+    pads: Vec<NoteOrCtrl>,
+    // Notes or controllers towards the controller, with their channel.
+    channel: u8,
+    numbers: Vec<NoteOrCtrl>,
+    colors: (LPXColor, LPXColor),
+    states: Vec<Cell<bool>>,
+    momentary: bool,
+}
+
+impl ToggleMemory {
+    pub fn new(pads: Vec<NoteOrCtrl>, channel: u8, numbers: Vec<NoteOrCtrl>, on: LPXColor, off: LPXColor) -> Self {
+        let states = vec![Cell::new(false); pads.len()];
+        ToggleMemory { pads, channel, numbers, colors: (on, off), states, momentary: false }
+    }
+
+    pub fn momentary(pads: Vec<NoteOrCtrl>, channel: u8, numbers: Vec<NoteOrCtrl>, on: LPXColor, off: LPXColor) -> Self {
+        ToggleMemory { momentary: true, ..Self::new(pads, channel, numbers, on, off) }
+    }
+
+    // Return a filter that switches toggles when their pad is pressed, and passes only these presses.
+    pub fn Press(&self) -> ToggleMemoryPress<'_> {
+        ToggleMemoryPress(self)
+    }
+
+    // Return a modifier that turns pad events into the state of their toggle towards the controller.
+    pub fn Send(&self) -> ToggleMemorySend<'_> {
+        ToggleMemorySend(self)
+    }
+
+    // Return a filter that stores the state the controller sends, and turns it into pad events.
+    pub fn Store(&self) -> ToggleMemoryStore<'_> {
+        ToggleMemoryStore(self)
+    }
+
+    // Return a modifier that turns pad events into the color of their toggle.
+    pub fn Lights(&self) -> ToggleMemoryLights<'_> {
+        ToggleMemoryLights(self)
+    }
+
+    // Return a generator that emits the colors of all toggles.
+    pub fn Restore(&self) -> FilterChain<'_> {
+        Chain!(ToggleMemoryRestore(self), self.Lights())
+    }
+
+    // Switch toggles with pads on the Launchpad X, sending the state to the controller.
+    pub fn Toggle(&self) -> FilterChain<'_> {
+        if self.momentary { return Chain!(LPXFilter(), self.Press(), self.Send(), CTR()); }
+        Chain!(LPXFilter(), self.Press(), Fork!(Chain!(self.Send(), CTR()), Chain!(self.Lights(), LPX())))
+    }
+
+    // Set toggles from the controller, showing the state on the Launchpad X.
+    pub fn Feedback(&self) -> FilterChain<'_> {
+        Chain!(CTRFilter(), self.Store(), self.Lights(), LPX())
+    }
+}
+
+pub struct ToggleMemoryPress<'a>(&'a ToggleMemory);
+impl<'a> FilterTrait for ToggleMemoryPress<'a> {
+    fn run(&self, evs: &mut EventStream) {
+        let toggles = self.0;
+        evs.retain(|ev| match NoteOrCtrl::find(&toggles.pads, 1, ev) {
+            Some((i, true)) => {
+                if !toggles.momentary { toggles.states[i].set(!toggles.states[i].get()); }
+                true
+            },
+            _ => false,
+        });
+    }
+}
+
+pub struct ToggleMemorySend<'a>(&'a ToggleMemory);
+impl<'a> FilterTrait for ToggleMemorySend<'a> {
+    fn run(&self, evs: &mut EventStream) {
+        let toggles = self.0;
+        for ev in evs.iter_mut() {
+            if let Some((i, _)) = NoteOrCtrl::find(&toggles.pads, 1, ev) {
+                let value = if toggles.momentary || toggles.states[i].get() { 127 } else { 0 };
+                *ev = toggles.numbers[i].event(toggles.channel, value);
+            }
+        }
+    }
+}
+
+pub struct ToggleMemoryStore<'a>(&'a ToggleMemory);
+impl<'a> FilterTrait for ToggleMemoryStore<'a> {
+    fn run(&self, evs: &mut EventStream) {
+        let toggles = self.0;
+        let mut pads = vec![];
+        for ev in evs.iter() {
+            if let Some((i, on)) = NoteOrCtrl::find(&toggles.numbers, toggles.channel, ev) {
+                toggles.states[i].set(on);
+                pads.push(toggles.pads[i].event(1, 127));
+            }
+        }
+        evs.clear();
+        evs.extend(pads);
+    }
+}
+
+pub struct ToggleMemoryLights<'a>(&'a ToggleMemory);
+impl<'a> FilterTrait for ToggleMemoryLights<'a> {
+    fn run(&self, evs: &mut EventStream) {
+        let toggles = self.0;
+        for ev in evs.iter_mut() {
+            if let Some((i, _)) = NoteOrCtrl::find(&toggles.pads, 1, ev) {
+                let color = if toggles.states[i].get() { toggles.colors.0 } else { toggles.colors.1 };
+                *ev = toggles.pads[i].event(1, color as u8);
+            }
+        }
+    }
+}
+
+pub struct ToggleMemoryRestore<'a>(&'a ToggleMemory);
+impl<'a> FilterTrait for ToggleMemoryRestore<'a> {
+    fn run(&self, evs: &mut EventStream) {
+        if evs.is_empty() { return; }
+        evs.clear();
+        for pad in self.0.pads.iter() {
+            evs.push(pad.event(1, 127));
+        }
+    }
+}
+
+// Memory of controller values we sent, to recognise them when they come back.
+// The Launchpad X stops fading a fader when it receives its value, so while it is fading (and sending
+// us the values along the way), we don't want to pass values back to it. Music applications often
+// echo the values they receive as well, which we don't need to pass back either.
+pub struct EchoMemory {
+    window: Duration,
+    // Time a value was last sent and the value the device has, by channel and controller number.
+    sent: Vec<Cell<Option<(Instant, i32)>>>,
+}
+
+impl EchoMemory {
+    pub fn new(window: Duration) -> Self {
+        EchoMemory { window, sent: vec![Cell::new(None); 16 * 128] }
+    }
+
+    // Return a filter that remembers the controller values passing through it.
+    pub fn Sent(&self) -> EchoMemorySent<'_> {
+        EchoMemorySent(&self.sent)
+    }
+
+    // Return a filter that drops values of controllers that were sent within the time window,
+    // and values that are the same as the one the device has.
+    pub fn Suppress(&self) -> EchoMemorySuppress<'_> {
+        EchoMemorySuppress(self.window, &self.sent)
+    }
+
+    // Return a filter that remembers the controller values passing through it as the ones the device has,
+    // without starting the time window, e.g. for stored values that are sent to the device again.
+    pub fn Restored(&self) -> EchoMemoryRestored<'_> {
+        EchoMemoryRestored(&self.sent)
+    }
+}
+
+fn echo_index(ev: &CtrlEventImpl) -> usize {
+    (ev.channel as usize & 0x0f) * 128 + (ev.ctrl as usize & 0x7f)
+}
+
+pub struct EchoMemorySent<'a>(&'a Vec<Cell<Option<(Instant, i32)>>>);
+impl<'a> FilterTrait for EchoMemorySent<'a> {
+    fn run(&self, evs: &mut EventStream) {
+        let now = Instant::now();
+        for ev in evs.iter() {
+            if let Event::Ctrl(ev) = ev {
+                self.0[echo_index(ev)].set(Some((now, ev.value)));
+            }
+        }
+    }
+}
+
+pub struct EchoMemoryRestored<'a>(&'a Vec<Cell<Option<(Instant, i32)>>>);
+impl<'a> FilterTrait for EchoMemoryRestored<'a> {
+    fn run(&self, evs: &mut EventStream) {
+        for ev in evs.iter() {
+            if let Event::Ctrl(ev) = ev {
+                // Values that were never sent aren't suppressed anyway.
+                let cell = &self.0[echo_index(ev)];
+                if let Some((sent, _)) = cell.get() { cell.set(Some((sent, ev.value))); }
+            }
+        }
+    }
+}
+
+pub struct EchoMemorySuppress<'a>(Duration, &'a Vec<Cell<Option<(Instant, i32)>>>);
+impl<'a> FilterTrait for EchoMemorySuppress<'a> {
+    fn run(&self, evs: &mut EventStream) {
+        let now = Instant::now();
+        evs.retain(|ev| match ev {
+            Event::Ctrl(ev) => {
+                let cell = &self.1[echo_index(ev)];
+                match cell.get() {
+                    Some((sent, value)) => {
+                        let pass = value != ev.value && now.duration_since(sent) >= self.0;
+                        // the device gets this value now, so a later echo of it is dropped too
+                        if pass { cell.set(Some((sent, ev.value))); }
+                        pass
+                    },
+                    None => true,
+                }
+            },
+            _ => true,
+        });
+    }
+}
+
+// Drop echoes of values we sent to the controller, except for LED colors (channels 1-3), which
+// the controller may well send back on purpose, e.g. to light a button that was pressed.
+pub fn EchoSuppress<'a>(echo: &'a EchoMemory) -> FilterChain<'a> {
+    Fork!(
+        ChannelsFilter(&[1, 2, 3]),
+        Chain!(Not!(ChannelsFilter(&[1, 2, 3])), echo.Suppress())
+    )
+}
+
+// Bank memory, for paging through banks (e.g. of eight tracks) with buttons.
+pub struct BankMemory {
+    count: usize,
+    current: Cell<usize>,
+}
+
+impl BankMemory {
+    pub fn new(count: usize) -> Self {
+        BankMemory { count, current: Cell::new(0) }
+    }
+
+    // Return a filter that moves a number of banks up or down, and passes events only when the
+    // bank changed (so that there is nothing to do at the first or last bank).
+    pub fn Step(&self, delta: i32) -> BankMemoryStep<'_> {
+        BankMemoryStep(self.count, &self.current, delta)
+    }
+
+    // Return a filter that passes events only when a bank is the current one.
+    pub fn Filter(&self, bank: usize) -> BankMemoryFilter<'_> {
+        BankMemoryFilter(&self.current, bank)
+    }
+}
+
+pub struct BankMemoryStep<'a>(usize, &'a Cell<usize>, i32);
+impl<'a> FilterTrait for BankMemoryStep<'a> {
+    fn run(&self, evs: &mut EventStream) {
+        if evs.is_empty() { return; }
+        let current = self.1.get();
+        let bank = (current as i64 + self.2 as i64).clamp(0, self.0 as i64 - 1) as usize;
+        if bank == current {
+            evs.clear();
+        } else {
+            self.1.set(bank);
+        }
+    }
+}
+
+pub struct BankMemoryFilter<'a>(&'a Cell<usize>, usize);
+impl<'a> FilterTrait for BankMemoryFilter<'a> {
+    fn run(&self, evs: &mut EventStream) {
+        if self.0.get() != self.1 {
+            evs.clear();
+        }
+    }
+}
+
+// Scene memory, so that we can return to the current scene after re-initialisation.
+pub struct SceneMemory(Cell<SceneNum>);
+
+impl SceneMemory {
+    pub fn new(scene: SceneNum) -> Self {
+        SceneMemory(Cell::new(scene))
+    }
+
+    // Return a filter that stores the scene number.
+    pub fn Store(&self, scene: SceneNum) -> SceneMemoryStore<'_> {
+        SceneMemoryStore(&self.0, scene)
+    }
+
+    // Return a generator that switches to the stored scene.
+    pub fn Restore(&self) -> SceneMemoryRestore<'_> {
+        SceneMemoryRestore(&self.0)
+    }
+
+    // Return a filter that passes events only when the stored scene is one of scenes.
+    pub fn Filter(&self, scenes: &'static [SceneNum]) -> SceneMemoryFilter<'_> {
+        SceneMemoryFilter(&self.0, scenes)
+    }
+}
+
+pub struct SceneMemoryStore<'a>(&'a Cell<SceneNum>, SceneNum);
+impl<'a> FilterTrait for SceneMemoryStore<'a> {
+    fn run(&self, evs: &mut EventStream) {
+        if !evs.is_empty() {
+            self.0.set(self.1);
+        }
+    }
+}
+
+pub struct SceneMemoryFilter<'a>(&'a Cell<SceneNum>, &'static [SceneNum]);
+impl<'a> FilterTrait for SceneMemoryFilter<'a> {
+    fn run(&self, evs: &mut EventStream) {
+        if !self.1.contains(&self.0.get()) {
+            evs.clear();
+        }
+    }
+}
+
+pub struct SceneMemoryRestore<'a>(&'a Cell<SceneNum>);
+impl<'a> FilterTrait for SceneMemoryRestore<'a> {
+    fn run(&self, evs: &mut EventStream) {
+        if !evs.is_empty() {
+            evs.clear();
+            evs.push(SceneSwitchEvent(self.0.get()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::{LaunchpadMiniMk3, LaunchpadProMk3};
+
+    fn all_messages() -> Vec<LpxSysEx> {
+        let fader = |i: u8| LPXFader { polarity: LPXPolarity::Bipolar, ctrl: 30 + i, color: LPXColor::Orange };
+        vec![
+            LpxSysEx::SelectLayout(13),
+            LpxSysEx::SetupFaders(LPXOrientation::Horizontal, (0..8).map(fader).collect()),
+            LpxSysEx::Lighting(vec![
+                (11, LPXLight::Static(5)), (12, LPXLight::Flashing(5, 9)),
+                (13, LPXLight::Pulsing(21)), (99, LPXLight::Rgb(127, 64, 0)),
+            ]),
+            LpxSysEx::VelocityCurve(3, 100),
+            LpxSysEx::ScrollText { looping: true, speed: 10, color: LPXLight::Static(5), text: "Hello".to_string() },
+            LpxSysEx::ScrollText { looping: false, speed: 20, color: LPXLight::Rgb(1, 2, 3), text: "LPX".to_string() },
+            LpxSysEx::StopText,
+            LpxSysEx::Brightness(100),
+            LpxSysEx::LedSleep(true),
+            LpxSysEx::LedSleep(false),
+            LpxSysEx::LedFeedback { internal: true, external: false },
+            LpxSysEx::Aftertouch(1, 2),
+            LpxSysEx::FaderVelocity(true),
+            LpxSysEx::ProgrammerMode(true),
+            LpxSysEx::DawMode(true),
+            LpxSysEx::DawMode(false),
+            LpxSysEx::ClearDaw { session: true, drumrack: false, ctrls: true },
+            LpxSysEx::SessionColor(LPXColor::Orange, LPXColor::Softwhite),
+            LpxSysEx::Query(0x00),
+        ]
+    }
+
+    #[test]
+    fn sysex_round_trip() {
+        for message in all_messages() {
+            assert_eq!(LpxSysEx::parse(&message.to_bytes()), Some(message.clone()), "{:?}", message);
+        }
+    }
+
+    #[test]
+    fn palette() {
+        assert_eq!(LPXColor::from_index(0), LPXColor::Black);
+        assert_eq!(LPXColor::from_index(19), LPXColor::Softgreen);
+        assert_eq!(LPXColor::from_index(127), LPXColor::Mahogany);
+        // Only the palette index (7 bits) is used.
+        assert_eq!(LPXColor::from_index(128 + 5), LPXColor::Red);
+        for (i, (color, rgb)) in LPX_PALETTE.iter().enumerate() {
+            assert_eq!(*color as usize, i);
+            assert_eq!(LPXColor::from_index(i as u8).rgb(), *rgb);
+        }
+
+        // Palette colors are their own nearest color, except where an earlier entry has the same RGB value.
+        assert_eq!(LPXColor::nearest(0x14, 0x2b, 0x00), LPXColor::Softgreen);
+        assert_eq!(LPXColor::nearest(0x00, 0xff, 0x19), LPXColor::Emerald);
+        assert_eq!(LPXColor::nearest(0xff, 0x00, 0x00), LPXColor::Red);
+        assert_eq!(LPXColor::nearest(0xff, 0x80, 0x00), LPXColor::Amber);
+        assert_eq!(LPXColor::nearest(0x01, 0x02, 0x03), LPXColor::Black);
+        for (color, (r, g, b)) in LPX_PALETTE.iter() {
+            let nearest = LPXColor::nearest(*r, *g, *b);
+            assert!(nearest as u8 <= *color as u8 && nearest.rgb() == (*r, *g, *b), "{:?}", color);
+        }
+    }
+
+    #[test]
+    fn parse_color() {
+        assert_eq!("softgreen".parse(), Ok(LPXColor::Softgreen));
+        assert_eq!("DarkGrey".parse(), Ok(LPXColor::Darkgrey));
+        assert_eq!("#FF0000".parse(), Ok(LPXColor::Red));
+        assert_eq!("#1e1e30".parse(), Ok(LPXColor::Darkslate));
+        for bad in ["", "mauve", "soft green", "#", "#ff000", "#ff00000", "#gg0000", "ff0000"].iter() {
+            assert_eq!(bad.parse::<LPXColor>(), Err(()), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn parse_light() {
+        assert_eq!("#ff8000".parse(), Ok(LPXLight::Rgb(127, 64, 0)));
+        assert_eq!(LPXLight::from_rgb8(1, 2, 255), LPXLight::Rgb(0, 1, 127));
+        assert_eq!("Red".parse(), Ok(LPXLight::Static(LPXColor::Red as u8)));
+        assert_eq!("#ff800".parse::<LPXLight>(), Err(()));
+        assert_eq!("infrared".parse::<LPXLight>(), Err(()));
+    }
+
+    #[test]
+    fn sysex_bytes_round_trip() {
+        let replies: &[&[u8]] = &[
+            &[0xf0, 0x00, 0x20, 0x29, 0x02, 0x0c, 0x00, 0x0d, 0xf7],
+            &[0xf0, 0x00, 0x20, 0x29, 0x02, 0x0c, 0x10, 0x01, 0xf7],
+            &[0xf0, 0x00, 0x20, 0x29, 0x02, 0x0c, 0x14, 0x09, 0x01, 0xf7],
+            &[0xf0, 0x00, 0x20, 0x29, 0x02, 0x0c, 0x03, 0x00, 0x59, 0x00, 0x03, 0x0b, 0x7f, 0x00, 0x00, 0xf7],
+        ];
+        for reply in replies {
+            let message = LpxSysEx::parse(reply).expect("known reply");
+            assert_eq!(&message.to_bytes()[..], *reply);
+        }
+    }
+
+    #[test]
+    fn sysex_known_bytes() {
+        assert_eq!(LpxSysEx::DawMode(true).to_bytes(), [0xf0, 0x00, 0x20, 0x29, 0x02, 0x0c, 0x10, 0x01, 0xf7]);
+        assert_eq!(LpxSysEx::SelectLayout(127).to_bytes(), [0xf0, 0x00, 0x20, 0x29, 0x02, 0x0c, 0x00, 0x7f, 0xf7]);
+        assert_eq!(LpxSysEx::parse(&[0xf0, 0x00, 0x20, 0x29, 0x02, 0x0c, 0x00, 0x0d, 0xf7]), Some(LpxSysEx::SelectLayout(13)));
+    }
+
+    #[test]
+    fn sysex_other_devices() {
+        let message = LpxSysEx::SelectLayout(LaunchpadProMk3.layout(DeviceLayout::Faders));
+        let bytes = message.to_device_bytes(&LaunchpadProMk3);
+        assert_eq!(bytes, [0xf0, 0x00, 0x20, 0x29, 0x02, 0x0e, 0x00, 0x01, 0x00, 0x00, 0xf7]);
+        assert_eq!(LpxSysEx::parse_device(&LaunchpadProMk3, &bytes), Some(message));
+
+        for message in all_messages() {
+            let bytes = message.to_device_bytes(&LaunchpadMiniMk3);
+            assert_eq!(bytes[5], 0x0d);
+            assert_eq!(LpxSysEx::parse_device(&LaunchpadMiniMk3, &bytes), Some(message.clone()), "{:?}", message);
+            assert_eq!(LpxSysEx::parse(&bytes), None);
+        }
+    }
+
+    #[test]
+    fn device_info() {
+        let reply = [0xf0, 0x7e, 0x00, 0x06, 0x02, 0x00, 0x20, 0x29, 0x03, 0x01, 0x00, 0x00, 0x00, 0x04, 0x00, 0x03, 0xf7];
+        let info = DeviceInfo::parse(&reply).expect("device inquiry reply");
+        assert!(info.is_device(&LaunchpadX));
+        assert_eq!(info.firmware(), 403);
+
+        // Launchpad Pro MK3, and a device with a one-byte manufacturer ID.
+        let reply = [0xf0, 0x7e, 0x00, 0x06, 0x02, 0x00, 0x20, 0x29, 0x23, 0x01, 0x00, 0x00, 0x00, 0x04, 0x05, 0x01, 0xf7];
+        let info = DeviceInfo::parse(&reply).expect("device inquiry reply");
+        assert!(!info.is_device(&LaunchpadX));
+        assert!(info.is_device(&LaunchpadProMk3));
+        let reply = [0xf0, 0x7e, 0x10, 0x06, 0x02, 0x41, 0x01, 0x02, 0x03, 0x04, 0x00, 0x00, 0x01, 0x00, 0xf7];
+        assert_eq!(DeviceInfo::parse(&reply).expect("device inquiry reply").manufacturer, [0x41]);
+
+        assert_eq!(DeviceInfo::parse(&DEVICE_INQUIRY), None);
+    }
+
+    #[test]
+    fn sysex_parse_rejects_invalid() {
+        // Other device (Launchpad Pro MK3), missing end byte, unknown command and truncated colorspec.
+        assert_eq!(LpxSysEx::parse(&[0xf0, 0x00, 0x20, 0x29, 0x02, 0x0e, 0x00, 0x00, 0xf7]), None);
+        assert_eq!(LpxSysEx::parse(&[0xf0, 0x00, 0x20, 0x29, 0x02, 0x0c, 0x00, 0x00]), None);
+        assert_eq!(LpxSysEx::parse(&[0xf0, 0x00, 0x20, 0x29, 0x02, 0x0c, 0x7e, 0x00, 0xf7]), None);
+        assert_eq!(LpxSysEx::parse(&[0xf0, 0x00, 0x20, 0x29, 0x02, 0x0c, 0x03, 0x03, 0x0b, 0x7f, 0xf7]), None);
+        // Queries only exist for settings.
+        assert_eq!(LpxSysEx::parse(&[0xf0, 0x00, 0x20, 0x29, 0x02, 0x0c, 0x7e, 0xf7]), None);
+        assert_eq!(LpxSysEx::parse(&[0xf0, 0x00, 0x20, 0x29, 0x02, 0x0c, 0x03, 0xf7]), None);
+        assert_eq!(LpxSysEx::parse(&[0xf0, 0x00, 0x20, 0x29, 0x02, 0x0c, 0x12, 0xf7]), None);
+        assert_eq!(LpxSysEx::parse(&[0xf0, 0x00, 0x20, 0x29, 0x02, 0x0c, 0x0a, 0xf7]), Some(LpxSysEx::Query(0x0a)));
+    }
+
+    #[test]
+    fn echo_memory() {
+        let values = |evs: &EventStream| -> Vec<(u32, i32)> {
+            evs.iter().map(|ev| match ev { Event::Ctrl(ev) => (ev.ctrl, ev.value), _ => panic!("not a ctrl") }).collect()
+        };
+
+        // Within the window, anything that was sent is dropped.
+        let echo = EchoMemory::new(Duration::from_secs(60));
+        let mut evs = EventStream::from(vec![CtrlEvent(0, 5, 30, 64)]);
+        echo.Sent().run(&mut evs);
+        let mut evs = EventStream::from(vec![CtrlEvent(0, 5, 30, 64), CtrlEvent(0, 5, 30, 70), CtrlEvent(0, 5, 31, 64)]);
+        echo.Suppress().run(&mut evs);
+        assert_eq!(values(&evs), [(31, 64)]);
+
+        // After it, only values that differ from what the device has are passed.
+        let echo = EchoMemory::new(Duration::from_secs(0));
+        let mut evs = EventStream::from(vec![CtrlEvent(0, 5, 30, 64)]);
+        echo.Sent().run(&mut evs);
+        let mut evs = EventStream::from(vec![CtrlEvent(0, 5, 30, 64), CtrlEvent(0, 5, 30, 70), CtrlEvent(0, 5, 30, 70)]);
+        echo.Suppress().run(&mut evs);
+        assert_eq!(values(&evs), [(30, 70)]);
+
+        // A value that was stored while the page was hidden is what the device has after restoring it,
+        // so the value sent before is passed again.
+        let echo = EchoMemory::new(Duration::from_secs(0));
+        let mut evs = EventStream::from(vec![CtrlEvent(0, 5, 30, 100)]);
+        echo.Sent().run(&mut evs);
+        let mut evs = EventStream::from(vec![CtrlEvent(0, 5, 30, 50), CtrlEvent(0, 5, 31, 50)]);
+        echo.Restored().run(&mut evs);
+        let mut evs = EventStream::from(vec![CtrlEvent(0, 5, 30, 100), CtrlEvent(0, 5, 30, 100), CtrlEvent(0, 5, 31, 50)]);
+        echo.Suppress().run(&mut evs);
+        assert_eq!(values(&evs), [(30, 100), (31, 50)]);
+    }
+
+    #[test]
+    fn bank_memory() {
+        let bank = BankMemory::new(3);
+        let step = |delta| {
+            let mut evs = EventStream::from(vec![CtrlEvent(0, 1, 94, 127)]);
+            bank.Step(delta).run(&mut evs);
+            !evs.is_empty()
+        };
+        let current = || (0..3).find(|i| {
+            let mut evs = EventStream::from(vec![CtrlEvent(0, 1, 94, 127)]);
+            bank.Filter(*i).run(&mut evs);
+            !evs.is_empty()
+        });
+
+        assert_eq!(current(), Some(0));
+        assert!(!step(-1));
+        assert!(step(1));
+        assert!(step(1));
+        assert!(!step(1));
+        assert_eq!(current(), Some(2));
+        assert!(step(-1));
+        assert_eq!(current(), Some(1));
+    }
+
+    #[test]
+    fn toggle_memory() {
+        let toggles = ToggleMemory::new(
+            vec![NoteOrCtrl::Note(11), NoteOrCtrl::Ctrl(19)], 5,
+            vec![NoteOrCtrl::Ctrl(70), NoteOrCtrl::Note(36)], LPXColor::Yellow, LPXColor::Darkgrey
+        );
+
+        // Pressing a pad switches it on, releasing it does nothing.
+        let mut evs = EventStream::from(vec![NoteOnEvent(1, 1, 11, 127), NoteOffEvent(1, 1, 11), CtrlEvent(1, 1, 19, 0)]);
+        toggles.Press().run(&mut evs);
+        assert_eq!(evs.len(), 1);
+        let mut sent = evs.clone();
+        toggles.Send().run(&mut sent);
+        assert_eq!(sent, vec![CtrlEvent(0, 5, 70, 127)]);
+        toggles.Lights().run(&mut evs);
+        assert_eq!(evs, vec![NoteOnEvent(0, 1, 11, LPXColor::Yellow as u8)]);
+
+        // The controller switches them back, and its other messages are dropped.
+        let mut evs = EventStream::from(vec![CtrlEvent(2, 5, 70, 0), NoteOnEvent(2, 5, 36, 127), CtrlEvent(2, 5, 71, 127)]);
+        toggles.Store().run(&mut evs);
+        toggles.Lights().run(&mut evs);
+        assert_eq!(evs, vec![NoteOnEvent(0, 1, 11, LPXColor::Darkgrey as u8), CtrlEvent(0, 1, 19, LPXColor::Yellow as i32)]);
+
+        let mut evs = EventStream::from(vec![CtrlEvent(0, 1, 0, 0)]);
+        toggles.Restore().run(&mut evs);
+        assert_eq!(evs.len(), 2);
+
+        // Momentary toggles send 127 on every press, and are only lit by the controller.
+        let toggles = ToggleMemory::momentary(vec![NoteOrCtrl::Note(11)], 5, vec![NoteOrCtrl::Ctrl(62)], LPXColor::Pink, LPXColor::Darkgrey);
+        for _ in 0..2 {
+            let mut evs = EventStream::from(vec![NoteOnEvent(1, 1, 11, 127), NoteOffEvent(1, 1, 11)]);
+            toggles.Toggle().run(&mut evs);
+            assert_eq!(evs, vec![CtrlEvent(2, 5, 62, 127)]);
+        }
+        let mut evs = EventStream::from(vec![CtrlEvent(2, 5, 62, 127)]);
+        toggles.Feedback().run(&mut evs);
+        assert_eq!(evs, vec![NoteOnEvent(1, 1, 11, LPXColor::Pink as u8)]);
+        let mut evs = EventStream::from(vec![NoteOnEvent(1, 1, 11, 127)]);
+        toggles.Toggle().run(&mut evs);
+        assert_eq!(evs, vec![CtrlEvent(2, 5, 62, 127)]);
+    }
+
+    #[test]
+    fn notes_memory() {
+        let notes = [11, 12, 13];
+        let mem = NotesMemory::new(&notes, Some(0));
+        let mut evs = EventStream::from(vec![
+            NoteOnEvent(2, 1, 11, 5), NoteOnEvent(2, 1, 12, 21), NoteOnEvent(2, 2, 12, 13),
+            NoteOffEvent(2, 1, 13), NoteOnEvent(2, 1, 14, 9),
+        ]);
+        mem.Store().run(&mut evs);
+        // A static color stops flashing.
+        let mut evs = EventStream::from(vec![NoteOnEvent(2, 1, 11, 45)]);
+        mem.Store().run(&mut evs);
+
+        mem.Restore().run(&mut evs);
+        assert_eq!(evs, vec![
+            NoteOnEvent(0, 1, 11, 45), NoteOnEvent(0, 1, 12, 21), NoteOnEvent(0, 2, 12, 13), NoteOnEvent(0, 1, 13, 0),
+        ]);
+    }
+
+    #[test]
+    fn led_memory() {
+        let mem = LedMemory::new(None);
+        mem.store_button(19, Some(0));
+        let mut evs = EventStream::from(vec![
+            NoteOnEvent(2, 1, 11, 5), CtrlEvent(2, 1, 91, 21), CtrlEvent(2, 3, 99, 13),
+            // Not an LED of the surface, or not a color.
+            NoteOnEvent(2, 1, 10, 9), CtrlEvent(2, 5, 92, 7),
+        ]);
+        mem.Store().run(&mut evs);
+
+        mem.Restore().run(&mut evs);
+        assert_eq!(evs, vec![
+            NoteOnEvent(0, 1, 11, 5), CtrlEvent(0, 1, 19, 0), CtrlEvent(0, 1, 91, 21), CtrlEvent(0, 3, 99, 13),
+        ]);
+    }
+
+    #[test]
+    fn led_framebuffer() {
+        let leds = LedFramebuffer::new(&LaunchpadX, Duration::from_millis(20), 400);
+        let lighting = |lights: Vec<(u8, LPXLight)>| Err(LpxSysEx::Lighting(lights).to_bytes());
+        let flush = |evs: Vec<Event<'static>>| {
+            let mut evs = EventStream::from(evs);
+            leds.Flush().run(&mut evs);
+            evs.iter().map(|ev| match ev {
+                Event::SysEx(ev) => Err(ev.data.to_vec()),
+                ev => Ok(ev.clone()),
+            }).collect::<Vec<_>>()
+        };
+        assert_eq!(flush(vec![
+            NoteOnEvent(0, 1, 11, 5), NoteOnEvent(0, 2, 11, 9), CtrlEvent(0, 1, 19, 21), CtrlEvent(0, 3, 91, 13),
+            // Not an LED.
+            CtrlEvent(0, 5, 30, 64),
+        ]), vec![Ok(CtrlEvent(0, 5, 30, 64)), lighting(vec![
+            (11, LPXLight::Flashing(5, 9)), (19, LPXLight::Static(21)), (91, LPXLight::Pulsing(13)),
+        ])]);
+        // Only changes are sent, a single LED with its own message.
+        assert_eq!(flush(vec![NoteOnEvent(0, 1, 11, 5), CtrlEvent(0, 1, 19, 21)]), vec![Ok(NoteOnEvent(0, 1, 11, 5))]);
+        assert_eq!(flush(vec![CtrlEvent(0, 1, 19, 21)]), vec![]);
+        assert_eq!(flush(vec![CtrlEvent(0, 1, 19, 5), CtrlEvent(0, 2, 19, 9)]), vec![Ok(CtrlEvent(0, 1, 19, 5)), Ok(CtrlEvent(0, 2, 19, 9))]);
+
+        // Messages sent otherwise are followed.
+        let mut evs = EventStream::from(vec![CtrlEvent(1, 1, 19, 0), CtrlEvent(2, 1, 29, 0)]);
+        leds.Track().run(&mut evs);
+        assert_eq!(flush(vec![CtrlEvent(0, 1, 19, 0), CtrlEvent(0, 1, 29, 0)]), vec![Ok(CtrlEvent(0, 1, 29, 0))]);
+
+        // Selecting a layout makes pads unknown, but not buttons.
+        let layout = LpxSysEx::SelectLayout(0).to_bytes();
+        assert_eq!(flush(vec![SysExEvent(0, Box::leak(layout.clone().into_boxed_slice())), NoteOnEvent(0, 1, 11, 5), CtrlEvent(0, 1, 19, 0)]),
+            vec![Err(layout), Ok(NoteOnEvent(0, 1, 11, 5))]);
+
+        // Only so many lighting SysEx messages are kept, after that each LED gets a message.
+        let mut i = 0;
+        while leds.messages.borrow().len() < LIGHTING_MESSAGES {
+            let (color, led) = ((i % 128) as u8, 13 + (i / 128) as u8);
+            assert_eq!(leds.set_lights(&[(12, LPXLight::Static(color)), (led, LPXLight::Static(color))]).len(), 1);
+            i += 1;
+        }
+        assert_eq!(leds.set_lights(&[(12, LPXLight::Flashing(1, 2)), (13, LPXLight::Pulsing(3))]),
+            vec![NoteOnEvent(0, 1, 12, 1), NoteOnEvent(0, 2, 12, 2), NoteOnEvent(0, 3, 13, 3)]);
+        assert_eq!(leds.set_lights(&[(12, LPXLight::Static(0)), (13, LPXLight::Static(0))]).len(), 1);
+        assert_eq!(leds.set_lights(&[(99, LPXLight::Rgb(1, 2, 3))]), vec![SysExEvent(0, leds.message(vec![(99, LPXLight::Rgb(1, 2, 3))], true).unwrap())]);
+        assert_eq!(leds.messages.borrow().len(), LIGHTING_MESSAGES + 1);
+    }
+
+    #[test]
+    fn led_framebuffer_limit() {
+        let leds = LedFramebuffer::new(&LaunchpadX, Duration::from_millis(50), 40);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let limit = |evs: Vec<Event<'static>>, now| {
+            let mut evs = EventStream::from(evs);
+            leds.limit(&mut evs, now);
+            evs
+        };
+        let tick = |now| {
+            let mut evs = EventStream::from(vec![CtrlEvent(3, 1, NOTIFY_TICK, 0)]);
+            leds.tick(&mut evs, now);
+            evs
+        };
+        // The second update of an LED comes too soon, and only two messages can be sent in the window.
+        assert_eq!(limit(vec![
+            CtrlEvent(2, 1, 19, 1), CtrlEvent(2, 1, 19, 2), CtrlEvent(2, 1, 29, 3), CtrlEvent(2, 1, 39, 4),
+            CtrlEvent(2, 1, 19, 5), CtrlEvent(2, 5, 30, 6),
+        ], at(0)), vec![CtrlEvent(2, 1, 19, 1), CtrlEvent(2, 1, 29, 3), CtrlEvent(2, 5, 30, 6)]);
+        assert!(leds.ticking().load(Ordering::Relaxed));
+        assert_eq!(tick(at(49)), vec![]);
+
+        assert_eq!(tick(at(50)), vec![CtrlEvent(2, 1, 39, 4), CtrlEvent(2, 1, 19, 5)]);
+        assert!(!leds.ticking().load(Ordering::Relaxed));
+
+        // Lighting an LED drops the update that was held back.
+        assert_eq!(limit(vec![CtrlEvent(2, 1, 19, 7)], at(60)), vec![]);
+        let mut evs = EventStream::from(vec![CtrlEvent(0, 1, 19, 8)]);
+        leds.Flush().run(&mut evs);
+        assert_eq!(tick(at(200)), vec![]);
+        assert!(!leds.ticking().load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn button_modes() {
+        let run = |filter: &dyn FilterTrait| {
+            let mut evs = EventStream::from(vec![CtrlEvent(0, 1, 0, 0)]);
+            filter.run(&mut evs);
+            evs.iter().cloned().collect::<Vec<_>>()
+        };
+        assert_eq!(run(&LPXButton(89, LPXColor::Red)), vec![CtrlEvent(1, 1, 89, 5)]);
+        assert_eq!(run(&LPXButtonMode(LpxPad { x: 0, y: 0 }, LPXColor::Red, LPXMode::Pulsing)), vec![NoteOnEvent(1, 3, 11, 5)]);
+        let flashing = run(&LPXButtonMode(89, LPXColor::Red, LPXMode::Flashing(LPXColor::Green)));
+        assert_eq!(flashing, vec![CtrlEvent(1, 1, 89, 5), CtrlEvent(1, 2, 89, 21)]);
+
+        // A stored button keeps flashing when it's restored.
+        let memory = CtrlsMemory::new(&[89], None);
+        memory.Store().run(&mut EventStream::from(flashing.clone()));
+        assert_eq!(run(&memory.Restore()), vec![CtrlEvent(0, 1, 89, 5), CtrlEvent(0, 2, 89, 21)]);
+    }
+
+    #[test]
+    fn grid() {
+        assert_eq!(LpxPad::from_xy(0, 0).map(LpxPad::number), Some(11));
+        assert_eq!(LpxPad::from_xy(7, 7).map(LpxPad::number), Some(88));
+        assert_eq!(LpxPad::from_number(35), Some(LpxPad { x: 4, y: 2 }));
+        assert_eq!(LpxPad::from_xy(8, 0), None);
+        assert_eq!(LpxPad::from_number(19), None);
+        assert_eq!(LpxPad::all().map(LpxPad::number).collect::<Vec<_>>(), LPX_PADS.to_vec());
+
+        assert_eq!(LpxButton::Up.ctrl(), 91);
+        assert_eq!(LpxButton::Session.xy(), (4, 8));
+        assert_eq!(LpxButton::Logo.number(), 99);
+        assert_eq!(LpxButton::Volume.ctrl(), 89);
+        assert_eq!(LpxButton::RecordArm.ctrl(), 19);
+        assert_eq!(LpxButton::scene(5), Some(LpxButton::Mute));
+        assert_eq!(LpxButton::Right.xy(), (3, 8));
+        assert_eq!(LpxButton::from_xy(3, 8), Some(LpxButton::Right));
+        assert_eq!(LpxButton::from_xy(8, 8), Some(LpxButton::Logo));
+        assert_eq!(LpxButton::from_number(11), None);
+        assert_eq!(LpxButton::from_ctrl(69), Some(LpxButton::SendA));
+        assert_eq!(LpxButton::from_ctrl(99 + 256), None);
+        assert!(LpxButton::ALL.iter().all(|button| LpxButton::from_ctrl(button.ctrl()) == Some(*button)));
+        let mut buttons: Vec<u32> = LpxButton::ALL.iter().map(|button| button.ctrl()).collect();
+        buttons.sort_unstable();
+        assert_eq!(buttons, LPX_BUTTONS.to_vec());
+
+        let press = |button: NoteOrCtrl, ev| {
+            let mut evs = EventStream::from(vec![ev]);
+            LPXButtonFilter(button).run(&mut evs);
+            !evs.is_empty()
+        };
+        assert!(press(LpxButton::Mute.into(), CtrlEvent(1, 1, 39, 127)));
+        assert!(!press(LpxButton::Mute.into(), CtrlEvent(1, 1, 39, 0)));
+        assert!(press(LpxPad { x: 1, y: 0 }.into(), NoteOnEvent(1, 1, 12, 100)));
+        assert!(!press(LpxPad { x: 1, y: 0 }.into(), NoteOnEvent(1, 1, 12, 0)));
+        assert!(!press(95.into(), CtrlEvent(2, 1, 95, 127)));
     }
 }
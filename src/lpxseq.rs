@@ -0,0 +1,484 @@
+// ALSA sequencer helpers for things that RMididings doesn't do (yet), like hotplugging.
+use std::error::Error;
+use std::ffi::CString;
+use std::fs;
+use std::process;
+use std::ptr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+extern crate alsa;
+use alsa::seq;
+extern crate alsa_sys;
+
+extern crate signal_hook;
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+
+use crate::lpxproc::{DeviceInfo, NOTIFY_CONNECTED, NOTIFY_QUIT, NOTIFY_TICK};
+use crate::profile::{DeviceProfile, PROFILES};
+
+// Watches for the Launchpad X to appear, connects it to our ports and notifies the patch.
+pub struct DeviceWatcher {
+    // Name of the RMididings client that owns the ports.
+    pub client_name: String,
+    pub device: DeviceSelector,
+    // Profile of the device we're running for.
+    pub profile: &'static dyn DeviceProfile,
+    // Port names of our RMididings client, for the DAW port and the MIDI port (which we only read).
+    pub in_port: String,
+    pub out_port: String,
+    pub midi_in_port: String,
+    pub notify_port: String,
+}
+
+impl DeviceWatcher {
+    // Start watching in a background thread.
+    // The device is connected to our inquiry port before returning, so we get its reply to the
+    // device inquiry of the init scene.
+    pub fn spawn(self) -> Result<(), Box<dyn Error>> {
+        let (alsaseq, ports) = self.open()?;
+        thread::Builder::new().name("device watcher".to_string()).spawn(move || {
+            if let Err(err) = self.run(&alsaseq, ports) {
+                println!("Error: device watcher stopped: {}", err);
+            }
+        })?;
+        Ok(())
+    }
+
+    // Open our sequencer client with ports for announcements and device inquiry replies.
+    fn open(&self) -> Result<(seq::Seq, WatcherPorts), Box<dyn Error>> {
+        let alsaseq = seq::Seq::open(None, None, false)?;
+        alsaseq.set_client_name(&CString::new(format!("{} watcher", self.client_name))?)?;
+        let announce = alsaseq.create_simple_port(
+            &CString::new("announce")?,
+            seq::PortCap::WRITE | seq::PortCap::NO_EXPORT,
+            seq::PortType::APPLICATION
+        )?;
+        let inquiry = alsaseq.create_simple_port(
+            &CString::new("inquiry")?,
+            seq::PortCap::WRITE | seq::PortCap::NO_EXPORT,
+            seq::PortType::APPLICATION
+        )?;
+        let client = alsaseq.client_id()?;
+
+        let subs = seq::PortSubscribe::empty()?;
+        subs.set_sender(seq::Addr::system_announce());
+        subs.set_dest(seq::Addr { client, port: announce });
+        alsaseq.subscribe_port(&subs)?;
+
+        if let Some(device) = self.device.find(&alsaseq)? {
+            subscribe(&alsaseq, device.daw.input, seq::Addr { client, port: inquiry })?;
+        }
+
+        Ok((alsaseq, WatcherPorts { client, announce, inquiry }))
+    }
+
+    fn run(&self, alsaseq: &seq::Seq, ports: WatcherPorts) -> Result<(), Box<dyn Error>> {
+        let mut input = alsaseq.input();
+        loop {
+            let ev = input.event_input()?;
+            match ev.get_type() {
+                seq::EventType::PortStart => {
+                    // Keep watching when connecting fails, so that the next hotplug still works.
+                    if let Some(addr) = ev.get_data::<seq::Addr>() {
+                        if let Err(err) = self.port_start(alsaseq, &ports, addr) {
+                            println!("Error: could not connect {}: {}", self.profile.name(), err);
+                        }
+                    }
+                },
+                seq::EventType::Sysex => {
+                    if let Some(info) = ev.get_ext().and_then(DeviceInfo::parse) {
+                        if let Err(err) = self.check(alsaseq, ports.announce, &info) {
+                            println!("Error: could not check {}: {}", self.profile.name(), err);
+                        }
+                    }
+                },
+                _ => (),
+            }
+        }
+    }
+
+    // Connect the device when one of its ports appeared, and notify the patch.
+    fn port_start(&self, alsaseq: &seq::Seq, ports: &WatcherPorts, addr: seq::Addr) -> Result<(), Box<dyn Error>> {
+        // With separate input and output ports, wait until both have appeared.
+        let device = match self.device.find(alsaseq)? {
+            Some(device) if device.has_addr(addr) => device,
+            _ => return Ok(()),
+        };
+        self.connect(alsaseq, &device)?;
+        // The MIDI port may appear later, only (re)initialise for the DAW port.
+        if !device.daw.has_addr(addr) { return Ok(()); }
+        println!("{} connected on {}", device.profile.name(), device.daw.input_name);
+        subscribe(alsaseq, device.daw.input, seq::Addr { client: ports.client, port: ports.inquiry })?;
+        notify(alsaseq, ports.announce, &self.client_name, &self.notify_port, NOTIFY_CONNECTED)?;
+        Ok(())
+    }
+
+    // Report the device's identity, and stop when it isn't the device of our profile.
+    // There is no minimum firmware, as it's not documented which version added DAW mode and the faders.
+    fn check(&self, alsaseq: &seq::Seq, port: i32, info: &DeviceInfo) -> Result<(), Box<dyn Error>> {
+        let name = self.profile.name();
+        if !info.is_device(self.profile) {
+            println!("Error: device is not a {} (manufacturer {:02x?}, family {:02x?}), stopping",
+                name, info.manufacturer, info.family);
+            notify(alsaseq, port, &self.client_name, &self.notify_port, NOTIFY_QUIT)?;
+        } else {
+            println!("{} firmware {}", name, info.firmware());
+        }
+        Ok(())
+    }
+
+    // Subscribe our in and out ports to the device.
+    fn connect(&self, alsaseq: &seq::Seq, device: &DevicePorts) -> Result<(), Box<dyn Error>> {
+        if let Some(in_port) = find_port(alsaseq, &self.client_name, &self.in_port)? {
+            subscribe(alsaseq, device.daw.input, in_port)?;
+        }
+        if let Some(out_port) = find_port(alsaseq, &self.client_name, &self.out_port)? {
+            subscribe(alsaseq, out_port, device.daw.output)?;
+        }
+        if let Some(midi) = &device.midi {
+            if let Some(in_port) = find_port(alsaseq, &self.client_name, &self.midi_in_port)? {
+                subscribe(alsaseq, midi.input, in_port)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Ports of the device watcher's sequencer client.
+struct WatcherPorts {
+    client: i32,
+    announce: i32,
+    inquiry: i32,
+}
+
+// How to find the Launchpad on the sequencer.
+#[derive(Clone)]
+pub enum DeviceSelector {
+    // Fixed DAW and (optional) MIDI ports, as 'client:port', of a device.
+    Ports(String, Option<String>, &'static dyn DeviceProfile),
+    // The first Launchpad found by USB ID or name, or only the one with this serial number,
+    // optionally only a specific device.
+    Discover(Option<String>, Option<&'static dyn DeviceProfile>),
+}
+
+// A sequencer port of a device, which is split in an input and an output port by PipeWire's ALSA bridge.
+pub struct DevicePort {
+    // Port to read from and port to write to.
+    pub input: seq::Addr,
+    pub output: seq::Addr,
+    // Their names, as 'client:port'.
+    pub input_name: String,
+    pub output_name: String,
+}
+
+impl DevicePort {
+    fn has_addr(&self, addr: seq::Addr) -> bool {
+        self.input == addr || self.output == addr
+    }
+}
+
+// Sequencer ports of a Launchpad.
+pub struct DevicePorts {
+    pub profile: &'static dyn DeviceProfile,
+    // The DAW port, for DAW mode, session and mixer.
+    pub daw: DevicePort,
+    // The MIDI port, for note and custom layouts.
+    pub midi: Option<DevicePort>,
+    pub serial: Option<String>,
+}
+
+impl DevicePorts {
+    fn has_addr(&self, addr: seq::Addr) -> bool {
+        self.daw.has_addr(addr) || matches!(&self.midi, Some(midi) if midi.has_addr(addr))
+    }
+}
+
+impl DeviceSelector {
+    // Find the ports of the Launchpad.
+    pub fn find(&self, alsaseq: &seq::Seq) -> Result<Option<DevicePorts>, Box<dyn Error>> {
+        match self {
+            DeviceSelector::Ports(daw, midi, profile) => {
+                let daw = match find_named_port(alsaseq, daw)? {
+                    Some(daw) => daw,
+                    None => return Ok(None),
+                };
+                let midi = match midi {
+                    Some(midi) => find_named_port(alsaseq, midi)?,
+                    None => None,
+                };
+                Ok(Some(DevicePorts { profile: *profile, daw, midi, serial: None }))
+            },
+            DeviceSelector::Discover(serial, profile) => {
+                for device in discover(alsaseq)? {
+                    if serial.is_some() && device.serial != *serial { continue; }
+                    if matches!(profile, Some(profile) if profile.model() != device.profile.model()) { continue; }
+                    return Ok(Some(device));
+                }
+                Ok(None)
+            },
+        }
+    }
+
+    // Find the device once, to connect our ports at startup.
+    pub fn find_once(&self) -> Result<Option<DevicePorts>, Box<dyn Error>> {
+        let alsaseq = seq::Seq::open(None, None, false)?;
+        self.find(&alsaseq)
+    }
+}
+
+// Find a port by its name, as 'client:port'.
+fn find_named_port(alsaseq: &seq::Seq, name: &str) -> Result<Option<DevicePort>, Box<dyn Error>> {
+    let (client_name, port_name) = name.split_once(':')
+        .ok_or_else(|| format!("invalid device port: {}", name))?;
+    Ok(find_port(alsaseq, client_name, port_name)?.map(|addr| DevicePort {
+        input: addr,
+        output: addr,
+        input_name: name.to_string(),
+        output_name: name.to_string(),
+    }))
+}
+
+// Find the ports of all Launchpad devices, by USB ID or else by name.
+fn discover(alsaseq: &seq::Seq) -> Result<Vec<DevicePorts>, Box<dyn Error>> {
+    let mut devices = vec![];
+    let cards = ClientCards::open()?;
+    for client in seq::ClientIter::new(alsaseq) {
+        let client_name = client.get_name()?;
+        let card = cards.card(client.get_client());
+        let usb_id = card.and_then(|card| read_trimmed(&format!("/proc/asound/card{}/usbid", card)));
+
+        // PipeWire's ALSA bridge has the ports of all devices in a single client.
+        for profile in PROFILES.iter().copied() {
+            let is_usb_device = usb_id.as_deref() == Some(profile.usb_id());
+            let [daw_alsa, daw_pipewire] = profile.daw_port();
+            let [midi_alsa, midi_pipewire] = profile.midi_port();
+
+            let (mut daw, mut midi) = (PortFinder::default(), PortFinder::default());
+            for port in seq::PortIter::new(alsaseq, client.get_client()) {
+                let port_name = port.get_name()?;
+                let name = format!("{}:{}", client_name, port_name);
+                if is_usb_device || client_name.contains(profile.name()) {
+                    if port_name.ends_with(daw_alsa) { daw.add(&port, name.clone()); }
+                    if port_name.ends_with(midi_alsa) { midi.add(&port, name.clone()); }
+                }
+                if port_name.contains(daw_pipewire) { daw.add(&port, name.clone()); }
+                if port_name.contains(midi_pipewire) { midi.add(&port, name); }
+            }
+
+            if let Some(daw) = daw.port() {
+                // The card's device is the USB interface, its parent the USB device with the serial number.
+                let serial = card.and_then(|card| read_trimmed(&format!("/sys/class/sound/card{}/device/../serial", card)));
+                devices.push(DevicePorts { profile, daw, midi: midi.port(), serial });
+            }
+        }
+    }
+    Ok(devices)
+}
+
+// Collects the input and output of a device port while scanning the ports of a client.
+#[derive(Default)]
+struct PortFinder {
+    input: Option<(seq::Addr, String)>,
+    output: Option<(seq::Addr, String)>,
+}
+
+impl PortFinder {
+    fn add(&mut self, port: &seq::PortInfo, name: String) {
+        let caps = port.get_capability();
+        if self.input.is_none() && caps.contains(seq::PortCap::READ | seq::PortCap::SUBS_READ) {
+            self.input = Some((port.addr(), name.clone()));
+        }
+        if self.output.is_none() && caps.contains(seq::PortCap::WRITE | seq::PortCap::SUBS_WRITE) {
+            self.output = Some((port.addr(), name));
+        }
+    }
+
+    fn port(self) -> Option<DevicePort> {
+        match (self.input, self.output) {
+            (Some((input, input_name)), Some((output, output_name))) =>
+                Some(DevicePort { input, output, input_name, output_name }),
+            _ => None,
+        }
+    }
+}
+
+// Sound cards of sequencer clients, from their client info. The alsa crate doesn't have the card
+// in its ClientInfo, so this uses its own sequencer handle.
+struct ClientCards(*mut alsa_sys::snd_seq_t);
+
+impl ClientCards {
+    fn open() -> Result<Self, Box<dyn Error>> {
+        let name = CString::new("default")?;
+        let mut handle = ptr::null_mut();
+        let err = unsafe { alsa_sys::snd_seq_open(&mut handle, name.as_ptr(), alsa_sys::SND_SEQ_OPEN_OUTPUT, 0) };
+        if err < 0 { return Err(alsa::Error::new("snd_seq_open", -err).into()); }
+        Ok(ClientCards(handle))
+    }
+
+    // Card of a client, None for clients that don't belong to a card (e.g. applications).
+    fn card(&self, client: i32) -> Option<i32> {
+        let mut info = ptr::null_mut();
+        unsafe {
+            if alsa_sys::snd_seq_client_info_malloc(&mut info) < 0 { return None; }
+            let card = match alsa_sys::snd_seq_get_any_client_info(self.0, client, info) {
+                0 => alsa_sys::snd_seq_client_info_get_card(info),
+                _ => -1,
+            };
+            alsa_sys::snd_seq_client_info_free(info);
+            if card >= 0 { Some(card) } else { None }
+        }
+    }
+}
+
+impl Drop for ClientCards {
+    fn drop(&mut self) {
+        unsafe { alsa_sys::snd_seq_close(self.0); }
+    }
+}
+
+fn read_trimmed(path: &str) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+// Watches for termination signals and asks the patch to quit, so it can clean up.
+pub struct SignalWatcher {
+    // Name of the RMididings client that owns the ports.
+    pub client_name: String,
+    // Port names of our internal ports, one for each device.
+    pub notify_ports: Vec<String>,
+}
+
+impl SignalWatcher {
+    // Start watching in a background thread.
+    pub fn spawn(self) -> Result<(), Box<dyn Error>> {
+        let mut signals = Signals::new([SIGINT, SIGTERM, SIGHUP])?;
+        thread::Builder::new().name("signal watcher".to_string()).spawn(move || {
+            for (i, _) in signals.forever().enumerate() {
+                // Exit right away when the patch didn't respond to the first signal.
+                if i > 0 { process::exit(1); }
+                if let Err(err) = self.quit() {
+                    println!("Error: {}", err);
+                    process::exit(1);
+                }
+            }
+        })?;
+        Ok(())
+    }
+
+    fn quit(&self) -> Result<(), Box<dyn Error>> {
+        let alsaseq = seq::Seq::open(None, Some(alsa::Direction::Playback), false)?;
+        alsaseq.set_client_name(&CString::new(format!("{} signals", self.client_name))?)?;
+        let port = alsaseq.create_simple_port(
+            &CString::new("notify")?,
+            seq::PortCap::READ | seq::PortCap::NO_EXPORT,
+            seq::PortType::APPLICATION
+        )?;
+        // Notify every port that's still there, a device may have been removed.
+        let mut notified = false;
+        for notify_port in self.notify_ports.iter() {
+            match notify(&alsaseq, port, &self.client_name, notify_port, NOTIFY_QUIT) {
+                Ok(true) => notified = true,
+                Ok(false) => println!("Error: port not found: {}", notify_port),
+                Err(err) => println!("Error: could not notify {}: {}", notify_port, err),
+            }
+        }
+        if !notified { return Err("no notify port found".into()); }
+        Ok(())
+    }
+}
+
+// Notifies the patch regularly while LED messages are held back, see `LedFramebuffer`.
+pub struct LedTicker {
+    // Name of the RMididings client that owns the ports.
+    pub client_name: String,
+    pub notify_port: String,
+    pub interval: Duration,
+    // Set by the patch while it has LED messages to send.
+    pub ticking: Arc<AtomicBool>,
+}
+
+impl LedTicker {
+    // Start ticking in a background thread.
+    pub fn spawn(self) -> Result<(), Box<dyn Error>> {
+        let alsaseq = seq::Seq::open(None, Some(alsa::Direction::Playback), false)?;
+        alsaseq.set_client_name(&CString::new(format!("{} ticker", self.client_name))?)?;
+        let port = alsaseq.create_simple_port(
+            &CString::new("notify")?,
+            seq::PortCap::READ | seq::PortCap::NO_EXPORT,
+            seq::PortType::APPLICATION
+        )?;
+        thread::Builder::new().name("led ticker".to_string()).spawn(move || {
+            // The notify port is looked up once, and again after sending to it failed.
+            let mut notify_port = None;
+            loop {
+                thread::sleep(self.interval);
+                if !self.ticking.load(Ordering::Relaxed) { continue; }
+                if notify_port.is_none() {
+                    notify_port = match find_port(&alsaseq, &self.client_name, &self.notify_port) {
+                        Ok(addr) => addr,
+                        Err(err) => {
+                            println!("Error: LED ticker stopped: {}", err);
+                            return;
+                        },
+                    };
+                }
+                if let Some(addr) = notify_port {
+                    if send_notification(&alsaseq, port, addr, NOTIFY_TICK).is_err() { notify_port = None; }
+                }
+            }
+        })?;
+        Ok(())
+    }
+}
+
+// Send a notification to our internal port, see `NotifyFilter`.
+fn notify(alsaseq: &seq::Seq, port: i32, client_name: &str, notify_port: &str, notification: u32) -> Result<bool, Box<dyn Error>> {
+    if let Some(notify_port) = find_port(alsaseq, client_name, notify_port)? {
+        send_notification(alsaseq, port, notify_port, notification)?;
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+fn send_notification(alsaseq: &seq::Seq, port: i32, notify_port: seq::Addr, notification: u32) -> Result<(), Box<dyn Error>> {
+    let mut ev = seq::Event::new(seq::EventType::Controller, &seq::EvCtrl {
+        channel: 0, param: notification, value: 0
+    });
+    ev.set_source(port);
+    ev.set_dest(notify_port);
+    ev.set_direct();
+    alsaseq.event_output_direct(&mut ev)?;
+    Ok(())
+}
+
+// List sequencer ports of Launchpad devices, as 'client:port' with their model and serial number.
+pub fn list_ports() -> Result<Vec<String>, Box<dyn Error>> {
+    let alsaseq = seq::Seq::open(None, None, false)?;
+    Ok(discover(&alsaseq)?.into_iter().map(|device| match device.serial {
+        Some(serial) => format!("{} ({}, serial {})", device.daw.input_name, device.profile.model(), serial),
+        None => format!("{} ({})", device.daw.input_name, device.profile.model()),
+    }).collect())
+}
+
+fn find_port(alsaseq: &seq::Seq, client_name: &str, port_name: &str) -> Result<Option<seq::Addr>, Box<dyn Error>> {
+    for client in seq::ClientIter::new(alsaseq) {
+        if client.get_name()? != client_name { continue; }
+        for port in seq::PortIter::new(alsaseq, client.get_client()) {
+            if port.get_name()? == port_name { return Ok(Some(port.addr())); }
+        }
+    }
+    Ok(None)
+}
+
+fn subscribe(alsaseq: &seq::Seq, sender: seq::Addr, dest: seq::Addr) -> Result<(), Box<dyn Error>> {
+    let subs = seq::PortSubscribe::empty()?;
+    subs.set_sender(sender);
+    subs.set_dest(dest);
+    // An existing subscription returns an error, which is fine.
+    let _ = alsaseq.subscribe_port(&subs);
+    Ok(())
+}
@@ -1,15 +1,28 @@
 #![allow(non_snake_case)]
+use std::env;
 use std::error::Error;
+use std::thread;
+use std::time::Duration;
 
 #[macro_use]
 extern crate rmididings;
 use rmididings::*;
 
+mod cli;
+use cli::{Args, USAGE};
+mod config;
+use config::{Config, DeviceConfig, Layout};
+mod profile;
+use profile::{find_profile, DeviceButton, DeviceLayout, DeviceProfile, LaunchpadX, SceneRow};
 mod lpxproc;
 use lpxproc::*;
+mod lpxseq;
+use lpxseq::*;
+mod mixer;
+use mixer::Mixer;
+mod programmer;
+use programmer::{Programmer, ProgrammerPage};
 use lpxproc::LPXColor::*;
-use lpxproc::LPXOrientation::*;
-use lpxproc::LPXPolarity::*;
 
 fn main() {
     match run() {
@@ -18,54 +31,216 @@ fn main() {
     }
 }
 
-// Button ctrls that we need to store because we use them (e.g. in the mixer views).
-const STORED_BTNS: [u32; 8] = [89, 79, 69, 59, 49, 39, 29, 19];
+// Internal port for notifications from our own threads, see `NotifyFilter`.
+const NOTIFY_PORT: &str = "Notify in";
 
 fn run() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(env::args().skip(1))?;
+    if args.help {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    if args.version {
+        println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+        return Ok(());
+    }
+    if args.list_ports {
+        for port in list_ports()? {
+            println!("{}", port);
+        }
+        return Ok(());
+    }
+
+    let mut config = match &args.config {
+        Some(path) => Config::load(path)?,
+        None => Config::load_default()?,
+    };
+    if let Some(client_name) = args.client_name {
+        config.ports.client_name = client_name;
+    }
+    if !config.devices.is_empty() && (args.device_port.is_some() || args.serial.is_some()) {
+        return Err("--device-port and --serial can't be used with devices from the configuration".into());
+    }
+    // A device or serial number on the command line overrides both from the configuration.
+    if let Some(device_port) = args.device_port {
+        config.ports.device = device_port;
+        config.ports.serial.clear();
+    }
+    if let Some(serial) = args.serial {
+        config.ports.device.clear();
+        config.ports.serial = serial;
+    }
+    // The configuration is used for the whole run, which allows static references into it.
+    let config: &'static Config = Box::leak(Box::new(config));
+    let devices = config.devices();
+
+    // Leave the Launchpad X devices in standalone mode when we're stopped.
+    SignalWatcher {
+        client_name: config.ports.client_name.clone(),
+        notify_ports: devices.iter().map(|device| device.port_name(NOTIFY_PORT)).collect(),
+    }.spawn()?;
+
+    // Each device has its own RMididings instance (and thereby its own scenes) in a thread.
+    let mut threads = vec![];
+    for device in devices {
+        let verbose = args.verbose;
+        threads.push(thread::Builder::new().name(format!("device {}", device.name)).spawn(move || {
+            run_device(config, &device, verbose).map_err(|err| err.to_string())
+        })?);
+    }
+    for thread in threads {
+        thread.join().map_err(|_| "device thread panicked")??;
+    }
+
+    Ok(())
+}
+
+fn run_device(config: &'static Config, device: &DeviceConfig, verbose: bool) -> Result<(), Box<dyn Error>> {
+    let ports = &config.ports;
+    let mixer = &config.mixer;
+
+    let model = find_profile(&device.model);
+    let selector = if !device.device.is_empty() {
+        let midi = Some(device.device_midi.clone()).filter(|midi| !midi.is_empty());
+        DeviceSelector::Ports(device.device.clone(), midi, model.unwrap_or(&LaunchpadX))
+    } else {
+        DeviceSelector::Discover(Some(device.serial.clone()).filter(|serial| !serial.is_empty()), model)
+    };
+    // When the Launchpad isn't there yet, the device watcher connects it later.
+    let found = selector.find_once()?;
+    let profile = found.as_ref().map(|found| found.profile).or(model).unwrap_or(&LaunchpadX);
+    if found.is_none() {
+        match device.name.as_str() {
+            "" => println!("Waiting for the {} to be connected", profile.name()),
+            name => println!("Waiting for {} {} to be connected", profile.name(), name),
+        }
+    }
+    let daw = found.as_ref().map(|found| &found.daw);
+    let midi = found.as_ref().and_then(|found| found.midi.as_ref());
+    let (device_in, device_out) = daw.map_or(("", ""), |daw| (&daw.input_name, &daw.output_name));
+    let device_midi_in = midi.map_or("", |midi| &midi.input_name);
+
+    let (launchpad_in, launchpad_out) = (device.port_name(&ports.launchpad_in), device.port_name(&ports.launchpad_out));
+    let launchpad_midi_in = device.port_name(&ports.launchpad_midi_in);
+    let (controller_in, controller_out) = (device.port_name(&ports.controller_in), device.port_name(&ports.controller_out));
+    let controller_midi_out = device.port_name(&ports.controller_midi_out);
+    let notify_port = device.port_name(NOTIFY_PORT);
+
+    let in_ports = [
+        [launchpad_in.as_str(), device_in],
+        [controller_in.as_str(), ""],
+        [notify_port.as_str(), ""],
+        [launchpad_midi_in.as_str(), device_midi_in],
+    ];
+    let out_ports = [
+        [launchpad_out.as_str(), device_out],
+        [controller_out.as_str(), ""],
+        [controller_midi_out.as_str(), ""],
+    ];
+
     let mut md = RMididings::new()?;
 
     md.config(ConfigArguments {
-        client_name: "Launchpad X Controller",
-        in_ports: &[
-            ["Lauchpad X in", "Launchpad X:Launchpad X MIDI 1"],
-            ["Controller in", ""],
-        ],
-        out_ports: &[
-            ["Launchpad X out", "Launchpad X:Launchpad X MIDI 1"],
-            ["Controller out", ""],
-        ],
+        client_name: &ports.client_name,
+        in_ports: &in_ports,
+        out_ports: &out_ports,
         data_offset: 1,
         scene_offset: 0,
         ..ConfigArguments::default()
     })?;
 
-    // Stored state of right buttons (which we use in mixer but want to have free to use in session mode).
-    let btnMem = CtrlsMemory::new(&STORED_BTNS, Some(Black as i32));
-    // Stored state of mixer values (so that we can use multiple mixers).
-    let btnMixerVol = CtrlsMemory::new(&[30, 31, 32, 33, 34, 35, 36, 37], Some(Black as i32));
-    let btnMixerPan = CtrlsMemory::new(&[38, 39, 40, 41, 42, 43, 44, 45], Some(Black as i32));
-    let btnMixerSdA = CtrlsMemory::new(&[46, 47, 48, 49, 50, 51, 52, 53], Some(Black as i32));
-    let btnMixerSdB = CtrlsMemory::new(&[54, 55, 56, 57, 58, 59, 60, 61], Some(Black as i32));
-    // The default of 0 is fine, except for pan, which we want to start in the middle.
-    btnMixerPan.store_all(Some(63));
+    // Reconnect when the Launchpad is plugged in again.
+    DeviceWatcher {
+        client_name: ports.client_name.clone(),
+        device: selector,
+        profile,
+        in_port: launchpad_in.clone(),
+        out_port: launchpad_out.clone(),
+        midi_in_port: launchpad_midi_in.clone(),
+        notify_port: notify_port.clone(),
+    }.spawn()?;
+
+    // Scenes of the enabled layouts.
+    let layoutScene = |layout| match layout {
+        Layout::Session => 1,
+        Layout::Mixer => 2,
+        Layout::Note => 3,
+        Layout::Custom => 4,
+        Layout::Programmer => 5,
+    };
+    let sessionEnabled = device.has_layout(Layout::Session);
+    let mixerEnabled = device.has_layout(Layout::Mixer);
+
+    // Buttons of this device.
+    let btnSession = profile.button(DeviceButton::Session);
+    let (btnNote, btnCustom) = (profile.button(DeviceButton::Note), profile.button(DeviceButton::Custom));
+    let btnProgrammer = profile.button(DeviceButton::Programmer);
+    // Right column, from the top.
+    let btnRight: Vec<u32> = (0..8).filter_map(SceneRow::new).map(|row| profile.button(DeviceButton::Scene(row))).collect();
+
+    // Current scene, to return to after re-initialising a reconnected Launchpad X.
+    let sceneMem = SceneMemory::new(layoutScene(device.layouts[0]));
+
+    // Values that we sent to the Controller, so that we don't interrupt fading faders with their echoes.
+    let ctrlEcho = EchoMemory::new(Duration::from_millis(config.echo_window));
+    // LEDs that the Launchpad X shows, so that only changes are sent when restoring LEDs, and so
+    // that LED messages from the Controller don't flood it.
+    let ledBuf = LedFramebuffer::new(profile, Duration::from_millis(config.led_window), config.led_rate);
+    LedTicker {
+        client_name: ports.client_name.clone(),
+        notify_port: notify_port.clone(),
+        interval: Duration::from_millis(config.led_window),
+        ticking: ledBuf.ticking(),
+    }.spawn()?;
+    // Mixer values of each track bank (so that we can use multiple mixers).
+    let mixerBanks = Mixer::new(profile, mixer, &ctrlEcho, &ledBuf);
+    // Pages of the programmer layout.
+    let programmer = Programmer::new(profile, config.programmer.pages.iter().map(ProgrammerPage::from_config).collect(), &ledBuf);
+    // Stored state of right buttons (which we use in mixer but want to have free to use in session mode),
+    // and of the arrows when the mixer uses them for track banks.
+    let mut storedBtns = config.stored_buttons.clone();
+    if let Some((previous, next)) = mixerBanks.arrows() {
+        storedBtns.extend(previous.iter().chain(next.iter()).filter(|button| !config.stored_buttons.contains(button)));
+    }
+    let storedBtns: &'static [u32] = Box::leak(storedBtns.into_boxed_slice());
+    // LEDs of the whole surface for each scene, restored when the scene is entered again. The session
+    // LEDs are stored in any scene (see control), and the stored buttons are cleared when leaving the mixer.
+    let sessionLeds = LedMemory::new(Some(Black as u8));
+    let noteLeds = LedMemory::new(None);
+    let customLeds = LedMemory::new(None);
+    for leds in [&sessionLeds, &noteLeds, &customLeds].iter() {
+        for button in storedBtns.iter() {
+            leds.store_button(*button, Some(Black as i32));
+        }
+    }
 
     md.run(RunArguments {
         scenes: &[
             &Scene { // 0
                 name: "init",
-                init: &Fork!(
-                    LPXDawMode!(1),
-                    LPXSelectLayout!(0),
-                    LPXSessionColor!(Black, Black),
-                    SceneSwitch(1)
+                // Entering DAW mode makes the LED framebuffer forget what the Launchpad X shows.
+                init: &Chain!(
+                    Fork!(
+                        // Check that this is the device of our profile, see DeviceWatcher.
+                        LPXDeviceInquiry(),
+                        LPXDawMode(profile, true),
+                        LPXSelectLayout(profile, DeviceLayout::Session),
+                        LPXSessionColor(profile, Black, Black),
+                        sceneMem.Restore()
+                    ),
+                    ledBuf.Track()
                 ),
                 ..Scene::default()
             },
             &Scene { // 1
                 name: "session",
+                init: &Fork!(
+                    Chain!(sceneMem.Store(1), Discard()),
+                    Chain!(sessionLeds.Restore(), ledBuf.Flush(), LPX())
+                ),
                 patch: &Fork!(
-                    Chain!(LPXButtonFilter(95), SceneSwitch(2)),
-                    NormalForward(&btnMem)
+                    LayoutSwitch(mixerEnabled, btnSession, 2),
+                    NormalForward(&sessionLeds, &ledBuf, &ctrlEcho)
                 ),
                 ..Scene::default()
             },
@@ -74,99 +249,163 @@ fn run() -> Result<(), Box<dyn Error>> {
                 subscenes: &[
                     &Scene { // 2.0
                         name: "volume",
-                        init: &Fork!(
-                            LPXButton(89, Softgreen),
-                            LPXSetupMixers!(Vertical, Unipolar, 30, Orange),
-                            Chain!(btnMixerVol.Restore(), Channel(5), LPX())
-                        ),
-                        patch: &Discard(),
-                        exit: &LPXButton(89, Softwhite),
+                        init: &mixerBanks.PageInit(0, btnRight[0]),
+                        patch: &mixerBanks.PagePatch(0, btnRight[0]),
+                        exit: &mixerBanks.PageExit(0, btnRight[0]),
                         ..Scene::default()
                     },
                     &Scene { // 2.1
                         name: "pan",
-                        init: &Fork!(
-                            LPXButton(79, Softgreen),
-                            LPXSetupMixers!(Horizontal, Bipolar, 38, Yellow),
-                            Chain!(btnMixerPan.Restore(), Channel(5), LPX())
-                        ),
-                        patch: &Discard(),
-                        exit: &LPXButton(79, Softwhite),
+                        init: &mixerBanks.PageInit(1, btnRight[1]),
+                        patch: &mixerBanks.PagePatch(1, btnRight[1]),
+                        exit: &mixerBanks.PageExit(1, btnRight[1]),
                         ..Scene::default()
                     },
                     &Scene { // 2.2
                         name: "send a",
-                        init: &Fork!(
-                            LPXButton(69, Softgreen),
-                            LPXSetupMixers!(Vertical, Unipolar, 46, Green),
-                            Chain!(btnMixerSdA.Restore(), Channel(5), LPX())
-                        ),
-                        patch: &Discard(),
-                        exit: &LPXButton(69, Softwhite),
+                        init: &mixerBanks.PageInit(2, btnRight[2]),
+                        patch: &mixerBanks.PagePatch(2, btnRight[2]),
+                        exit: &mixerBanks.PageExit(2, btnRight[2]),
                         ..Scene::default()
                     },
                     &Scene { // 2.3
                         name: "send b",
-                        init: &Fork!(
-                            LPXButton(59, Softgreen),
-                            LPXSetupMixers!(Vertical, Unipolar, 54, Blue),
-                            Chain!(btnMixerSdB.Restore(), Channel(5), LPX())
-                        ),
-                        patch: &Discard(),
-                        exit: &LPXButton(59, Softwhite),
+                        init: &mixerBanks.PageInit(3, btnRight[3]),
+                        patch: &mixerBanks.PagePatch(3, btnRight[3]),
+                        exit: &mixerBanks.PageExit(3, btnRight[3]),
+                        ..Scene::default()
+                    },
+                    &Scene { // 2.4
+                        name: "stop clip",
+                        init: &mixerBanks.PageInit(4, btnRight[4]),
+                        patch: &mixerBanks.PagePatch(4, btnRight[4]),
+                        exit: &mixerBanks.PageExit(4, btnRight[4]),
+                        ..Scene::default()
+                    },
+                    &Scene { // 2.5
+                        name: "mute",
+                        init: &mixerBanks.PageInit(5, btnRight[5]),
+                        patch: &mixerBanks.PagePatch(5, btnRight[5]),
+                        exit: &mixerBanks.PageExit(5, btnRight[5]),
+                        ..Scene::default()
+                    },
+                    &Scene { // 2.6
+                        name: "solo",
+                        init: &mixerBanks.PageInit(6, btnRight[6]),
+                        patch: &mixerBanks.PagePatch(6, btnRight[6]),
+                        exit: &mixerBanks.PageExit(6, btnRight[6]),
+                        ..Scene::default()
+                    },
+                    &Scene { // 2.7
+                        name: "record arm",
+                        init: &mixerBanks.PageInit(7, btnRight[7]),
+                        patch: &mixerBanks.PagePatch(7, btnRight[7]),
+                        exit: &mixerBanks.PageExit(7, btnRight[7]),
                         ..Scene::default()
                     }
                 ],
                 init: &Fork!(
-                    LPXSelectLayout!(13),
-                    LPXSessionColor!(Orange, Softwhite),
+                    Chain!(sceneMem.Store(2), Discard()),
+                    // The layout is selected by the subscene.
+                    LPXSessionColor(profile, Orange, Softwhite),
                     // Setup right buttons for switching mixer subscenes.
-                    LPXButton(89, Softwhite),
-                    LPXButton(79, Softwhite),
-                    LPXButton(69, Softwhite),
-                    LPXButton(59, Softwhite),
-                    LPXButton(49, Black),
-                    LPXButton(39, Black),
-                    LPXButton(29, Black),
-                    LPXButton(19, Black)
+                    Chain!(
+                        Fork!(
+                            LPXButton(btnRight[0], Softwhite),
+                            LPXButton(btnRight[1], Softwhite),
+                            LPXButton(btnRight[2], Softwhite),
+                            LPXButton(btnRight[3], Softwhite),
+                            LPXButton(btnRight[4], Softwhite),
+                            LPXButton(btnRight[5], Softwhite),
+                            LPXButton(btnRight[6], Softwhite),
+                            LPXButton(btnRight[7], Softwhite)
+                        ),
+                        ledBuf.Flush(), LPX()
+                    )
                 ),
                 patch: &Fork!(
-                    MixerForward(&btnMem),
-                    // Switch to mixer subscene when pressing one of the four right buttons.
-                    Chain!(LPXButtonFilter(95), SceneSwitch(1)),
-                    Chain!(LPXButtonFilter(89), SubSceneSwitch(0)),
-                    Chain!(LPXButtonFilter(79), SubSceneSwitch(1)),
-                    Chain!(LPXButtonFilter(69), SubSceneSwitch(2)),
-                    Chain!(LPXButtonFilter(59), SubSceneSwitch(3))
+                    mixerBanks.Forward(storedBtns),
+                    // Switch to mixer subscene when pressing one of the right buttons.
+                    LayoutSwitch(sessionEnabled, btnSession, 1),
+                    Chain!(LPXButtonFilter(btnRight[0]), SubSceneSwitch(0)),
+                    Chain!(LPXButtonFilter(btnRight[1]), SubSceneSwitch(1)),
+                    Chain!(LPXButtonFilter(btnRight[2]), SubSceneSwitch(2)),
+                    Chain!(LPXButtonFilter(btnRight[3]), SubSceneSwitch(3)),
+                    Chain!(LPXButtonFilter(btnRight[4]), SubSceneSwitch(4)),
+                    Chain!(LPXButtonFilter(btnRight[5]), SubSceneSwitch(5)),
+                    Chain!(LPXButtonFilter(btnRight[6]), SubSceneSwitch(6)),
+                    Chain!(LPXButtonFilter(btnRight[7]), SubSceneSwitch(7))
                 ),
-                exit: &Fork!(
-                    LPXSessionColor!(Black, Black),
-                    Chain!(btnMem.Restore(), LPX())
-                ),
-                ..Scene::default()
+                exit: &LPXSessionColor(profile, Black, Black),
             },
             &Scene { // 3
                 name: "note",
+                init: &Fork!(
+                    Chain!(sceneMem.Store(3), Discard()),
+                    Chain!(noteLeds.Restore(), ledBuf.Flush(), LPX())
+                ),
                 patch: &Fork!(
-                    Chain!(LPXButtonFilter(95), SceneSwitch(1)),
-                    NormalForward(&btnMem)
+                    LayoutSwitch(sessionEnabled, btnSession, 1),
+                    Chain!(NotifyFilter(NOTIFY_CONNECTED), LPXSelectLayout(profile, DeviceLayout::Note)),
+                    NormalForward(&noteLeds, &ledBuf, &ctrlEcho)
                 ),
                 ..Scene::default()
             },
             &Scene { // 4
                 name: "custom",
+                init: &Fork!(
+                    Chain!(sceneMem.Store(4), Discard()),
+                    Chain!(customLeds.Restore(), ledBuf.Flush(), LPX())
+                ),
                 patch: &Fork!(
-                    Chain!(LPXButtonFilter(95), SceneSwitch(1)),
-                    NormalForward(&btnMem)
+                    LayoutSwitch(sessionEnabled, btnSession, 1),
+                    Chain!(NotifyFilter(NOTIFY_CONNECTED), LPXSelectLayout(profile, DeviceLayout::Custom)),
+                    NormalForward(&customLeds, &ledBuf, &ctrlEcho)
                 ),
                 ..Scene::default()
             },
+            &Scene { // 5
+                name: "programmer",
+                init: &Fork!(
+                    Chain!(sceneMem.Store(5), Discard()),
+                    programmer.Init()
+                ),
+                patch: &Chain!(
+                    Fork!(
+                        programmer.Patch(),
+                        ProgrammerLayoutSwitch(profile, sessionEnabled, btnSession, DeviceLayout::Session, 1),
+                        ProgrammerLayoutSwitch(profile, device.has_layout(Layout::Note), btnNote, DeviceLayout::Note, 3),
+                        ProgrammerLayoutSwitch(profile, device.has_layout(Layout::Custom), btnCustom, DeviceLayout::Custom, 4)
+                    ),
+                    ledBuf.Track()
+                ),
+                exit: &programmer.Exit(),
+                ..Scene::default()
+            },
         ],
         control: &Fork!(
-            Chain!(LPXButtonFilter(96), SceneSwitch(3)),
-            Chain!(LPXButtonFilter(97), SceneSwitch(4)),
+            Chain!(Not!(NotifyFilter(NOTIFY_TICK)), Log(verbose), Discard()),
+            // Notes and custom layout data come from the MIDI port, in any scene but the programmer layout.
+            Chain!(LPXMidiFilter(), sceneMem.Filter(&[1, 2, 3, 4]), CTRMidi()),
+            // Re-initialise the current scene when the Launchpad was reconnected.
+            Chain!(NotifyFilter(NOTIFY_CONNECTED), Fork!(LPXDawMode(profile, true), SceneSwitch(0))),
+            // Send LED messages from the Controller that were held back.
+            Chain!(NotifyFilter(NOTIFY_TICK), ledBuf.Tick(), LPX(), ledBuf.Track()),
+            // Restore the Launchpad to standalone mode before quitting.
+            Chain!(NotifyFilter(NOTIFY_QUIT), Fork!(LPXStandalone(profile, &btnRight), Quit())),
+            // Switch layouts with their buttons, the programmer layout has its own switches in its patch.
+            Chain!(sceneMem.Filter(&[1, 2, 3, 4]), Fork!(
+                LayoutSwitch(device.has_layout(Layout::Note), btnNote, 3),
+                LayoutSwitch(device.has_layout(Layout::Custom), btnCustom, 4),
+                LayoutSwitch(device.has_layout(Layout::Programmer), btnProgrammer, 5)
+            )),
+            // Follow the state from the Controller of the programmer layout pads, also when they're not shown.
+            Chain!(CTRFilter(), programmer.Store(), Discard()),
+            // Store the session LEDs that the Controller sets in any scene, to show them when the session
+            // layout is entered again.
+            Chain!(CTRFilter(), sessionLeds.Store(), Discard()),
             // Store mixer values from both LPX and Controller.
-            Chain!(ChannelFilter(5), btnMixerVol.Store(), btnMixerPan.Store(), btnMixerSdA.Store(), btnMixerSdB.Store(), Discard())
+            mixerBanks.Store()
         ),
         ..RunArguments::default()
     })?;
@@ -174,22 +413,46 @@ fn run() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-fn NormalForward<'a>(btnMem: &'a CtrlsMemory) -> FilterChain<'a> {
+// Generator: switch to the scene of a layout with a button, when the layout is enabled.
+fn LayoutSwitch<'a>(enabled: bool, button: u32, scene: SceneNum) -> FilterChain<'a> {
+    if enabled {
+        Chain!(LPXButtonFilter(button), SceneSwitch(scene))
+    } else {
+        Chain!(Discard())
+    }
+}
+
+// Generator: leave the programmer layout for the scene of a layout with a button (from either port),
+// selecting the layout on the device, which doesn't do this itself in the programmer layout.
+fn ProgrammerLayoutSwitch<'a>(profile: &dyn DeviceProfile, enabled: bool, button: u32, layout: DeviceLayout, scene: SceneNum) -> FilterChain<'a> {
+    if enabled {
+        Chain!(
+            Fork!(LPXFilter(), LPXMidiFilter()), LPXPressFilter(button.into()),
+            Fork!(LPXSelectLayout(profile, layout), SceneSwitch(scene))
+        )
+    } else {
+        Chain!(Discard())
+    }
+}
+
+// Generator: clear the right buttons we lit, reset session colors and leave DAW mode.
+fn LPXStandalone<'a>(profile: &dyn DeviceProfile, btnRight: &[u32]) -> FilterChain<'a> {
+    let black = LPXLight::Static(Black as u8);
+    let leds: Vec<_> = btnRight.iter().map(|button| (*button, black)).collect();
     Fork!(
-        // forward messages from LPX to controller and vice versa
-        Chain!(LPXFilter(), CTR()),
-        Chain!(CTRFilter(), LPX()),
-        // also store state of right buttons in session mode when controller sends it
-        Chain!(CTRFilter(), ChannelFilter(1), btnMem.Store(), Discard())
+        LPXLighting(profile, &leds),
+        LPXSessionColor(profile, Black, Black),
+        LPXDawMode(profile, false)
     )
 }
 
-fn MixerForward<'a>(btnMem: &'a CtrlsMemory) -> FilterChain<'a> {
+fn NormalForward<'a>(leds: &'a LedMemory, ledBuf: &'a LedFramebuffer, echo: &'a EchoMemory) -> FilterChain<'a> {
     Fork!(
-        // forward messages, but as we use the right buttons otherwise in the mixer view, don't pass
-        // them through to the controller, and store incoming right button changes for the session view
-        Chain!(CTRFilter(), Not!(Chain!(ChannelFilter(1), CtrlsFilter(&STORED_BTNS))), LPX()),
-        Chain!(LPXFilter(), Not!(Chain!(ChannelFilter(1), CtrlsFilter(&STORED_BTNS))), CTR()),
-        Chain!(CTRFilter(), ChannelFilter(1), btnMem.Store(), Discard())
+        // forward messages from LPX to controller and vice versa, without echoes from the controller
+        Chain!(LPXFilter(), echo.Sent(), CTR()),
+        Chain!(CTRFilter(), EchoSuppress(echo), ledBuf.Limit(), LPX(), ledBuf.Track()),
+        // also store the LEDs (including flashing or pulsing) that the controller sets, to restore them
+        // when the scene is entered again (also after the Launchpad X was reconnected)
+        Chain!(CTRFilter(), leds.Store(), Discard())
     )
 }
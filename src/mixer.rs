@@ -0,0 +1,343 @@
+// Mixer layouts, with banks of eight tracks that the arrow buttons page through.
+// The Launchpad X faders always send the CCs of the fader banks on LPX_MIXER_CHANNEL, towards the
+// Controller these are moved to the channel and CCs of the current track bank.
+// Besides the four fader pages, there are four pages with toggles (stop clip, which is momentary, mute,
+// solo and record arm) on the bottom row of pads of the session layout.
+use rmididings::proc::*;
+
+use crate::config::{MixerConfig, TrackBank};
+use crate::lpxproc::*;
+use crate::lpxproc::LPXColor::*;
+use crate::lpxproc::LPXOrientation::*;
+use crate::lpxproc::LPXPolarity::*;
+use crate::profile::{DeviceButton, DeviceLayout, DeviceProfile};
+
+// Row of pads of the session layout with the toggles, which go from left to right.
+const TOGGLE_ROW: u8 = 0;
+
+// Orientation and polarity of the volume, pan, send A and send B pages, after which come the toggle pages.
+const PAGES: [(LPXOrientation, LPXPolarity); 4] = [
+    (Vertical, Unipolar),
+    (Horizontal, Bipolar),
+    (Vertical, Unipolar),
+    (Vertical, Unipolar),
+];
+
+pub struct Mixer<'a> {
+    profile: &'static dyn DeviceProfile,
+    config: &'static MixerConfig,
+    banks: Vec<TrackBank>,
+    // Channels of all track banks.
+    channels: &'static [u8],
+    bank: BankMemory,
+    // Controller numbers of each page of each track bank, towards the controller.
+    ctrls: Vec<Vec<&'static [u32]>>,
+    // Stored values of each page of each track bank, by Launchpad X controller number.
+    values: Vec<Vec<CtrlsMemory<'static>>>,
+    // Toggles of each toggle page of each track bank.
+    toggles: Vec<Vec<ToggleMemory>>,
+    echo: &'a EchoMemory,
+    leds: &'a LedFramebuffer,
+}
+
+impl<'a> Mixer<'a> {
+    pub fn new(profile: &'static dyn DeviceProfile, config: &'static MixerConfig, echo: &'a EchoMemory, leds: &'a LedFramebuffer) -> Self {
+        let banks = config.track_banks();
+        let mut channels: Vec<u8> = banks.iter().map(|bank| bank.channel).collect();
+        channels.sort_unstable();
+        channels.dedup();
+        // Like the configuration, these are used for the whole run.
+        let ctrls: Vec<&'static [u32]> = config.fader_banks().iter()
+            .map(|(_, bank)| &*Box::leak(bank.ctrls().into_boxed_slice()))
+            .collect();
+        let bankCtrls = banks.iter().map(|bank| {
+            ctrls.iter().map(|ctrls| {
+                let ctrls: Vec<u32> = ctrls.iter().map(|ctrl| ctrl + bank.offset as u32).collect();
+                &*Box::leak(ctrls.into_boxed_slice())
+            }).collect()
+        }).collect();
+        let values = banks.iter().map(|_| {
+            let values: Vec<_> = ctrls.iter().map(|ctrls| CtrlsMemory::new(ctrls, Some(Black as i32))).collect();
+            // The default of 0 is fine, except for pan, which we want to start in the middle.
+            values[1].store_all(Some(63));
+            values
+        }).collect();
+        let toggles = banks.iter().map(|bank| {
+            config.toggle_banks().iter().map(|(name, toggles)| {
+                let pads = (0..8).filter_map(|x| LpxPad::from_xy(x, TOGGLE_ROW)).map(NoteOrCtrl::from).collect();
+                let numbers = toggles.numbers().iter().map(|number| number + bank.offset as u32).map(|number| match toggles.is_note() {
+                    true => NoteOrCtrl::Note(number as u8),
+                    false => NoteOrCtrl::Ctrl(number),
+                }).collect();
+                // Stopping a clip is an action rather than a state, which the controller shows.
+                match *name {
+                    "stop_clip" => ToggleMemory::momentary(pads, bank.channel, numbers, toggles.color, toggles.color_off),
+                    _ => ToggleMemory::new(pads, bank.channel, numbers, toggles.color, toggles.color_off),
+                }
+            }).collect()
+        }).collect();
+        Mixer {
+            profile,
+            config,
+            bank: BankMemory::new(banks.len()),
+            banks,
+            channels: Box::leak(channels.into_boxed_slice()),
+            ctrls: bankCtrls,
+            values,
+            toggles,
+            echo,
+            leds,
+        }
+    }
+
+    // Arrow buttons for the previous and next bank, when there is more than one.
+    pub fn arrows(&self) -> Option<([u32; 2], [u32; 2])> {
+        if self.banks.len() < 2 { return None; }
+        let button = |button| self.profile.button(button);
+        Some((
+            [button(DeviceButton::Up), button(DeviceButton::Left)],
+            [button(DeviceButton::Down), button(DeviceButton::Right)],
+        ))
+    }
+
+    // Generator: set up the faders or toggles of a page (and its button) with the values of the current bank.
+    pub fn PageInit(&self, page: usize, button: u32) -> FilterChain<'_> {
+        let mut filters: Vec<Box<dyn FilterTrait + '_>> = vec![];
+        if let Some((orientation, polarity)) = PAGES.get(page) {
+            let (_, faders) = self.config.fader_banks()[page];
+            filters.push(Box::new(LPXSelectLayout(self.profile, DeviceLayout::Faders)));
+            filters.push(Box::new(LPXSetupFaders(self.profile, *orientation, &faders.lpx_faders(*polarity))));
+        } else {
+            filters.push(Box::new(LPXSelectLayout(self.profile, DeviceLayout::Session)));
+        }
+        // The button of the page pulses in the color of the bank, and flashes red when pads arm tracks.
+        let toggles = page.checked_sub(PAGES.len()).and_then(|i| self.config.toggle_banks().get(i).map(|(name, _)| *name));
+        let mode = if toggles == Some("record_arm") { LPXMode::Flashing(Red) } else { LPXMode::Pulsing };
+        for (i, bank) in self.banks.iter().enumerate() {
+            let values: FilterChain<'_> = match PAGES.get(page) {
+                // The device gets the stored values, which may have changed while the page was hidden.
+                Some(_) => Chain!(self.values[i][page].Restore(), Fork!(
+                    Chain!(Channel(LPX_MIXER_CHANNEL), LPX()),
+                    Chain!(CtrlOffset(bank.offset as i32), Channel(bank.channel), self.echo.Restored(), Discard())
+                )),
+                None => Chain!(self.toggles[i][page - PAGES.len()].Restore(), LPX()),
+            };
+            filters.push(Box::new(Chain!(
+                self.bank.Filter(i),
+                Fork!(LPXButtonMode(button, bank.color, mode), values)
+            )));
+            // Light the arrows that lead to another bank.
+            if let Some((previous, next)) = self.arrows() {
+                let color = |lit| if lit { Softwhite } else { Black };
+                filters.push(Box::new(Chain!(
+                    self.bank.Filter(i),
+                    Fork!(
+                        LPXButton(previous[0], color(i > 0)),
+                        LPXButton(previous[1], color(i > 0)),
+                        LPXButton(next[0], color(i + 1 < self.banks.len())),
+                        LPXButton(next[1], color(i + 1 < self.banks.len()))
+                    )
+                )));
+            }
+        }
+        // Only send the LEDs that change (e.g. the arrows stay lit when switching banks).
+        Chain!(FilterChain::new(ConnectionType::Fork, filters), self.leds.Flush(), LPX())
+    }
+
+    // Patch of a page: show values from the controller, and switch banks with the arrows.
+    pub fn PagePatch(&self, page: usize, button: u32) -> FilterChain<'_> {
+        let mut filters: Vec<Box<dyn FilterTrait + '_>> = vec![];
+        for (i, bank) in self.banks.iter().enumerate() {
+            if page >= PAGES.len() {
+                // Toggle with the bottom row of pads, and show the state from the controller.
+                let toggles = &self.toggles[i][page - PAGES.len()];
+                filters.push(Box::new(Chain!(self.bank.Filter(i), Fork!(toggles.Toggle(), toggles.Feedback()))));
+                continue;
+            }
+            // Forward mixer values from the controller for the faders that are shown, so that they follow
+            // e.g. automation, except for faders that were just moved on the Launchpad X.
+            filters.push(Box::new(Chain!(
+                self.bank.Filter(i),
+                CTRFilter(), TypeFilter!(Ctrl), ChannelFilter(bank.channel), CtrlsFilter(self.ctrls[i][page]),
+                self.echo.Suppress(), CtrlOffset(-(bank.offset as i32)), ChannelMap(bank.channel, LPX_MIXER_CHANNEL), LPX()
+            )));
+        }
+        if page >= PAGES.len() {
+            // The other pads (the rows above) still launch clips, as in the session layout.
+            let (first, last) = (LpxPad { x: 0, y: TOGGLE_ROW + 1 }.number(), LpxPad { x: 7, y: 7 }.number());
+            filters.push(Box::new(Fork!(
+                Chain!(LPXFilter(), TypeFilter!(Note), ChannelFilter(1), KeyRangeFilter(first, last), CTR()),
+                Chain!(CTRFilter(), TypeFilter!(Note), ChannelsFilter(&[1, 2, 3]), KeyRangeFilter(first, last), LPX())
+            )));
+        }
+        if let Some((previous, next)) = self.arrows() {
+            filters.push(Box::new(Chain!(
+                Fork!(
+                    Chain!(Fork!(LPXButtonFilter(previous[0]), LPXButtonFilter(previous[1])), self.bank.Step(-1)),
+                    Chain!(Fork!(LPXButtonFilter(next[0]), LPXButtonFilter(next[1])), self.bank.Step(1))
+                ),
+                self.PageInit(page, button)
+            )));
+        }
+        Chain!(FilterChain::new(ConnectionType::Fork, filters), self.leds.Track())
+    }
+
+    // Generator: leave a page (the LEDs of the next scene are restored when it is entered).
+    pub fn PageExit(&self, _page: usize, button: u32) -> FilterChain<'_> {
+        Chain!(LPXButton(button, Softwhite), self.leds.Flush(), LPX())
+    }
+
+    // Forward messages, but as we use the right buttons (and arrows) otherwise in the mixer view, don't
+    // pass them through to the controller (mixer values from the controller are forwarded by PagePatch).
+    pub fn Forward(&self, storedBtns: &'static [u32]) -> FilterChain<'_> {
+        let mut filters: Vec<Box<dyn FilterTrait + '_>> = vec![
+            Box::new(Chain!(
+                CTRFilter(), Not!(Chain!(ChannelsFilter(&[1, 2, 3]), CtrlsFilter(storedBtns))),
+                Not!(ChannelsFilter(self.channels)), EchoSuppress(self.echo), self.leds.Limit(), LPX(), self.leds.Track()
+            )),
+            Box::new(Chain!(
+                LPXFilter(), Not!(Chain!(ChannelsFilter(&[1, 2, 3]), CtrlsFilter(storedBtns))),
+                Not!(ChannelFilter(LPX_MIXER_CHANNEL)), self.echo.Sent(), CTR()
+            )),
+        ];
+        for (i, bank) in self.banks.iter().enumerate() {
+            filters.push(Box::new(Chain!(
+                self.bank.Filter(i), LPXFilter(), ChannelFilter(LPX_MIXER_CHANNEL),
+                CtrlOffset(bank.offset as i32), ChannelMap(LPX_MIXER_CHANNEL, bank.channel), self.echo.Sent(), CTR()
+            )));
+        }
+        FilterChain::new(ConnectionType::Fork, filters)
+    }
+
+    // Store mixer values from both LPX (for the current bank) and Controller (for any bank).
+    pub fn Store(&self) -> FilterChain<'_> {
+        let mut filters: Vec<Box<dyn FilterTrait + '_>> = vec![];
+        for (i, bank) in self.banks.iter().enumerate() {
+            for page in 0..PAGES.len() {
+                filters.push(Box::new(Chain!(
+                    Fork!(
+                        Chain!(self.bank.Filter(i), LPXFilter(), ChannelFilter(LPX_MIXER_CHANNEL)),
+                        Chain!(
+                            CTRFilter(), TypeFilter!(Ctrl), ChannelFilter(bank.channel), CtrlsFilter(self.ctrls[i][page]),
+                            CtrlOffset(-(bank.offset as i32))
+                        )
+                    ),
+                    self.values[i][page].Store(), Discard()
+                )));
+            }
+            for toggles in self.toggles[i].iter() {
+                filters.push(Box::new(Chain!(CTRFilter(), toggles.Store(), Discard())));
+            }
+        }
+        FilterChain::new(ConnectionType::Fork, filters)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::profile::LaunchpadX;
+    use std::time::Duration;
+
+    // Like the configuration in main, the mixer configuration is kept for the whole test.
+    fn mixer_config(toml: &str) -> &'static MixerConfig {
+        let config: &'static Config = Box::leak(Box::new(toml::from_str(toml).unwrap()));
+        &config.mixer
+    }
+
+    // Control changes (port, channel, ctrl, value) that a filter outputs.
+    fn ctrls(filter: &dyn FilterTrait, evs: Vec<Event<'static>>) -> Vec<(usize, u8, u32, i32)> {
+        let mut evs = EventStream::from(evs);
+        filter.run(&mut evs);
+        evs.iter().filter_map(|ev| match ev {
+            Event::Ctrl(ev) => Some((ev.port, ev.channel, ev.ctrl, ev.value)),
+            _ => None,
+        }).collect()
+    }
+
+    #[test]
+    fn channel_banks() {
+        let config = mixer_config("[[mixer.track_banks]]\n[[mixer.track_banks]]\nchannel = 6");
+        let echo = EchoMemory::new(Duration::from_secs(0));
+        let leds = LedFramebuffer::new(&LaunchpadX, Duration::from_millis(20), 400);
+        let mixer = Mixer::new(&LaunchpadX, config, &echo, &leds);
+        let (store, patch, forward) = (mixer.Store(), mixer.PagePatch(0, 89), mixer.Forward(&[]));
+        assert_eq!(mixer.arrows(), Some(([91, 93], [92, 94])));
+
+        // The first bank is shown, with its stored values.
+        let init = ctrls(&mixer.PageInit(0, 89), vec![CtrlEvent(0, 1, 0, 0)]);
+        assert_eq!(init, (30..38).map(|ctrl| (1, 5, ctrl, 0)).collect::<Vec<_>>());
+
+        // Values from the Controller are stored for any bank, but only shown for the current one.
+        for ev in [CtrlEvent(2, 5, 30, 100), CtrlEvent(2, 6, 31, 90)].iter() {
+            ctrls(&store, vec![ev.clone()]);
+        }
+        assert_eq!(ctrls(&patch, vec![CtrlEvent(2, 5, 30, 100)]), [(1, 5, 30, 100)]);
+        assert_eq!(ctrls(&patch, vec![CtrlEvent(2, 6, 31, 90)]), []);
+        assert_eq!(ctrls(&forward, vec![CtrlEvent(1, 5, 32, 10)]), [(2, 5, 32, 10)]);
+
+        // The arrows switch banks, and show the values of the other bank.
+        let next = ctrls(&patch, vec![CtrlEvent(1, 1, 94, 127)]);
+        assert!(next.contains(&(1, 5, 31, 90)) && next.contains(&(1, 5, 30, 0)), "{:?}", next);
+        assert_eq!(ctrls(&patch, vec![CtrlEvent(2, 6, 31, 91)]), [(1, 5, 31, 91)]);
+        assert_eq!(ctrls(&patch, vec![CtrlEvent(2, 5, 30, 101)]), []);
+        assert_eq!(ctrls(&forward, vec![CtrlEvent(1, 5, 32, 10)]), [(2, 6, 32, 10)]);
+        assert!(ctrls(&patch, vec![CtrlEvent(1, 1, 92, 127)]).is_empty());
+    }
+
+    #[test]
+    fn offset_banks() {
+        let config = mixer_config(r#"
+            [[mixer.track_banks]]
+            [[mixer.track_banks]]
+            offset = 64
+            [mixer.volume]
+            ctrl = 0
+            color = "orange"
+            [mixer.pan]
+            ctrl = 8
+            color = "yellow"
+            [mixer.send_a]
+            ctrl = 16
+            color = "green"
+            [mixer.send_b]
+            ctrl = 24
+            color = "blue"
+            [mixer.stop_clip]
+            ctrl = 32
+            color = "pink"
+            [mixer.mute]
+            ctrl = 40
+            color = "yellow"
+            [mixer.solo]
+            ctrl = 48
+            color = "blue"
+            [mixer.record_arm]
+            ctrl = 56
+            color = "red"
+        "#);
+        let echo = EchoMemory::new(Duration::from_secs(0));
+        let leds = LedFramebuffer::new(&LaunchpadX, Duration::from_millis(20), 400);
+        let mixer = Mixer::new(&LaunchpadX, config, &echo, &leds);
+        let (store, patch, forward) = (mixer.Store(), mixer.PagePatch(0, 89), mixer.Forward(&[]));
+
+        // A fader move on the first bank, that the Controller echoes.
+        assert_eq!(ctrls(&forward, vec![CtrlEvent(1, 5, 0, 100)]), [(2, 5, 0, 100)]);
+        assert_eq!(ctrls(&patch, vec![CtrlEvent(2, 5, 0, 100)]), []);
+
+        // The second bank has its CCs moved by the offset.
+        ctrls(&store, vec![CtrlEvent(2, 5, 65, 90)]);
+        let next = ctrls(&patch, vec![CtrlEvent(1, 1, 94, 127)]);
+        assert!(next.contains(&(1, 5, 1, 90)), "{:?}", next);
+        assert_eq!(ctrls(&forward, vec![CtrlEvent(1, 5, 2, 10)]), [(2, 5, 66, 10)]);
+        assert_eq!(ctrls(&patch, vec![CtrlEvent(2, 5, 66, 20)]), [(1, 5, 2, 20)]);
+
+        // Automation that changed the first bank while it was hidden is shown when it comes back,
+        // after which the value the fader had before is passed again.
+        ctrls(&store, vec![CtrlEvent(2, 5, 0, 50)]);
+        let previous = ctrls(&patch, vec![CtrlEvent(1, 1, 93, 127)]);
+        assert!(previous.contains(&(1, 5, 0, 50)), "{:?}", previous);
+        assert_eq!(ctrls(&patch, vec![CtrlEvent(2, 5, 0, 100)]), [(1, 5, 0, 100)]);
+    }
+}
@@ -0,0 +1,190 @@
+// Device profiles, for what differs between the Launchpad models that work like the Launchpad X.
+// See the programmer's reference manual of each device.
+
+use crate::lpxproc::LpxButton;
+
+// Layouts that we select on the device.
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum DeviceLayout {
+    Session,
+    Note,
+    Custom,
+    Faders,
+    Programmer,
+}
+
+// Buttons that we use, the same function has a different button number on some devices.
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum DeviceButton {
+    Up,
+    Down,
+    Left,
+    Right,
+    Session,
+    Note,
+    Custom,
+    // Button that we use for the programmer layout.
+    Programmer,
+    // Button in the right column.
+    Scene(SceneRow),
+}
+
+// Row of a button in the right column, counted from the top (0-7), which is checked when it's made.
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub struct SceneRow(LpxButton);
+
+impl SceneRow {
+    pub fn new(row: u8) -> Option<Self> {
+        LpxButton::scene(row).map(SceneRow)
+    }
+}
+
+pub trait DeviceProfile: Sync {
+    // Name of the device, as in its sequencer client name.
+    fn name(&self) -> &'static str;
+    // Name in the configuration file.
+    fn model(&self) -> &'static str;
+    // USB vendor and product ID, as in /proc/asound/card*/usbid.
+    fn usb_id(&self) -> &'static str;
+    // Family code in the device inquiry reply.
+    fn family(&self) -> [u8; 2];
+    // Last byte of the SysEx header (F0 00 20 29 02 xx).
+    fn sysex_device(&self) -> u8;
+    fn layout(&self, layout: DeviceLayout) -> u8;
+    // Data following the layout in the layout select SysEx.
+    fn layout_suffix(&self) -> &'static [u8] { &[] }
+    fn button(&self, button: DeviceButton) -> u32 {
+        match button {
+            DeviceButton::Up => LpxButton::Up.ctrl(),
+            DeviceButton::Down => LpxButton::Down.ctrl(),
+            DeviceButton::Left => LpxButton::Left.ctrl(),
+            DeviceButton::Right => LpxButton::Right.ctrl(),
+            DeviceButton::Session => LpxButton::Session.ctrl(),
+            DeviceButton::Note => LpxButton::Note.ctrl(),
+            DeviceButton::Custom => LpxButton::Custom.ctrl(),
+            DeviceButton::Programmer => LpxButton::CaptureMidi.ctrl(),
+            DeviceButton::Scene(SceneRow(button)) => button.ctrl(),
+        }
+    }
+    // Whether a CC is one of the buttons around the grid, by default the top row (91-98) and right column (19-89).
+    fn is_button(&self, ctrl: u32) -> bool {
+        matches!(LpxButton::from_ctrl(ctrl), Some(button) if button != LpxButton::Logo)
+    }
+    // Names of the DAW and MIDI ports, as ALSA ("MIDI n") and PipeWire name them.
+    fn daw_port(&self) -> [&'static str; 2];
+    fn midi_port(&self) -> [&'static str; 2];
+    // Number of faders in the fader layouts.
+    fn faders(&self) -> u8 { 8 }
+}
+
+pub struct LaunchpadX;
+impl DeviceProfile for LaunchpadX {
+    fn name(&self) -> &'static str { "Launchpad X" }
+    fn model(&self) -> &'static str { "launchpad-x" }
+    fn usb_id(&self) -> &'static str { "1235:0103" }
+    fn family(&self) -> [u8; 2] { [0x03, 0x01] }
+    fn sysex_device(&self) -> u8 { 0x0c }
+    fn layout(&self, layout: DeviceLayout) -> u8 {
+        match layout {
+            DeviceLayout::Session => 0,
+            DeviceLayout::Note => 1,
+            DeviceLayout::Custom => 4,
+            DeviceLayout::Faders => 13,
+            DeviceLayout::Programmer => 127,
+        }
+    }
+    fn daw_port(&self) -> [&'static str; 2] { ["MIDI 1", "LPX DAW"] }
+    fn midi_port(&self) -> [&'static str; 2] { ["MIDI 2", "LPX MIDI"] }
+}
+
+// The Mini MK3 has Drums, Keys and User buttons where the Launchpad X has Note, Custom and Capture MIDI.
+pub struct LaunchpadMiniMk3;
+impl DeviceProfile for LaunchpadMiniMk3 {
+    fn name(&self) -> &'static str { "Launchpad Mini MK3" }
+    fn model(&self) -> &'static str { "launchpad-mini-mk3" }
+    fn usb_id(&self) -> &'static str { "1235:0113" }
+    fn family(&self) -> [u8; 2] { [0x13, 0x01] }
+    fn sysex_device(&self) -> u8 { 0x0d }
+    fn layout(&self, layout: DeviceLayout) -> u8 {
+        match layout {
+            DeviceLayout::Session => 0,
+            DeviceLayout::Note => 5,
+            DeviceLayout::Custom => 6,
+            DeviceLayout::Faders => 13,
+            DeviceLayout::Programmer => 127,
+        }
+    }
+    fn button(&self, button: DeviceButton) -> u32 {
+        match button {
+            DeviceButton::Note => LpxButton::Custom.ctrl(),
+            DeviceButton::Custom => LpxButton::CaptureMidi.ctrl(),
+            // Drums
+            DeviceButton::Programmer => LpxButton::Note.ctrl(),
+            _ => LaunchpadX.button(button),
+        }
+    }
+    fn daw_port(&self) -> [&'static str; 2] { ["MIDI 1", "LPMiniMK3 DAW"] }
+    fn midi_port(&self) -> [&'static str; 2] { ["MIDI 2", "LPMiniMK3 MIDI"] }
+}
+
+// The Pro MK3 has arrows in the left column and a page number in the layout select.
+pub struct LaunchpadProMk3;
+impl DeviceProfile for LaunchpadProMk3 {
+    fn name(&self) -> &'static str { "Launchpad Pro MK3" }
+    fn model(&self) -> &'static str { "launchpad-pro-mk3" }
+    fn usb_id(&self) -> &'static str { "1235:0123" }
+    fn family(&self) -> [u8; 2] { [0x23, 0x01] }
+    fn sysex_device(&self) -> u8 { 0x0e }
+    fn layout(&self, layout: DeviceLayout) -> u8 {
+        match layout {
+            DeviceLayout::Session => 0,
+            DeviceLayout::Faders => 1,
+            DeviceLayout::Custom => 3,
+            DeviceLayout::Note => 4,
+            DeviceLayout::Programmer => 17,
+        }
+    }
+    fn layout_suffix(&self) -> &'static [u8] { &[0x00, 0x00] }
+    fn button(&self, button: DeviceButton) -> u32 {
+        match button {
+            DeviceButton::Up => 80,
+            DeviceButton::Down => 70,
+            DeviceButton::Left => 91,
+            DeviceButton::Right => 92,
+            DeviceButton::Session => 93,
+            DeviceButton::Note => 94,
+            DeviceButton::Custom => 96,
+            // Sequencer
+            DeviceButton::Programmer => 97,
+            _ => LaunchpadX.button(button),
+        }
+    }
+    // Shift and the top row (90-98), left (10-80) and right (19-89) column and the two bottom rows (101-108, 1-8).
+    fn is_button(&self, ctrl: u32) -> bool {
+        (90..=98).contains(&ctrl) || ((10..=89).contains(&ctrl) && matches!(ctrl % 10, 0 | 9))
+            || (101..=108).contains(&ctrl) || (1..=8).contains(&ctrl)
+    }
+    fn daw_port(&self) -> [&'static str; 2] { ["MIDI 3", "LPProMK3 DAW"] }
+    fn midi_port(&self) -> [&'static str; 2] { ["MIDI 1", "LPProMK3 MIDI"] }
+}
+
+// All supported devices.
+pub static PROFILES: [&dyn DeviceProfile; 3] = [&LaunchpadX, &LaunchpadMiniMk3, &LaunchpadProMk3];
+
+// Profile by its name in the configuration file.
+pub fn find_profile(model: &str) -> Option<&'static dyn DeviceProfile> {
+    PROFILES.iter().copied().find(|profile| profile.model() == model)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scene_buttons() {
+        let buttons: Vec<u32> = (0..8).filter_map(SceneRow::new).map(|row| LaunchpadX.button(DeviceButton::Scene(row))).collect();
+        assert_eq!(buttons, [89, 79, 69, 59, 49, 39, 29, 19]);
+        assert_eq!(SceneRow::new(8), None);
+        assert_eq!(SceneRow::new(255), None);
+    }
+}
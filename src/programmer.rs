@@ -0,0 +1,327 @@
+// Programmer layout, in which we own every pad and LED instead of the firmware's layouts.
+// Its pages are built from pad handlers, which tell what a pad sends to the Controller when it's
+// pressed, and how it's lit. The buttons of the right column select the page.
+use std::cell::{Cell, RefCell};
+
+use rmididings::proc::*;
+
+use crate::config::{ProgrammerPadConfig, ProgrammerPageConfig};
+use crate::lpxproc::*;
+use crate::lpxproc::LPXColor::*;
+use crate::profile::{DeviceLayout, DeviceProfile};
+
+// What a pad does on a page.
+pub trait PadHandler {
+    // Lighting of the pad.
+    fn light(&self) -> LPXLight;
+    // The pad was pressed or released, return the event for the Controller (if any).
+    fn press(&self, pressed: bool) -> Option<Event<'static>>;
+    // An event from the Controller, e.g. to follow the state of a toggle.
+    fn feedback(&self, _ev: &Event) {}
+}
+
+// Pad that sends a note or CC while it's pressed (127, and 0 when released).
+pub struct MomentaryPad {
+    number: NoteOrCtrl,
+    channel: u8,
+    lights: (LPXLight, LPXLight),
+    pressed: Cell<bool>,
+}
+
+impl MomentaryPad {
+    pub fn new(number: NoteOrCtrl, channel: u8, on: LPXLight, off: LPXLight) -> Self {
+        MomentaryPad { number, channel, lights: (on, off), pressed: Cell::new(false) }
+    }
+}
+
+impl PadHandler for MomentaryPad {
+    fn light(&self) -> LPXLight {
+        if self.pressed.get() { self.lights.0 } else { self.lights.1 }
+    }
+
+    fn press(&self, pressed: bool) -> Option<Event<'static>> {
+        self.pressed.set(pressed);
+        Some(self.number.event(self.channel, if pressed { 127 } else { 0 }))
+    }
+}
+
+// Pad that switches on and off with each press (sending 127 or 0), and follows the Controller.
+pub struct TogglePad {
+    number: NoteOrCtrl,
+    channel: u8,
+    lights: (LPXLight, LPXLight),
+    on: Cell<bool>,
+}
+
+impl TogglePad {
+    pub fn new(number: NoteOrCtrl, channel: u8, on: LPXLight, off: LPXLight) -> Self {
+        TogglePad { number, channel, lights: (on, off), on: Cell::new(false) }
+    }
+}
+
+impl PadHandler for TogglePad {
+    fn light(&self) -> LPXLight {
+        if self.on.get() { self.lights.0 } else { self.lights.1 }
+    }
+
+    fn press(&self, pressed: bool) -> Option<Event<'static>> {
+        if !pressed { return None; }
+        self.on.set(!self.on.get());
+        Some(self.number.event(self.channel, if self.on.get() { 127 } else { 0 }))
+    }
+
+    fn feedback(&self, ev: &Event) {
+        if let Some((_, on)) = NoteOrCtrl::find(&[self.number], self.channel, ev) {
+            self.on.set(on);
+        }
+    }
+}
+
+// A page of pads with their handlers, pads without one stay dark.
+pub struct ProgrammerPage {
+    // Color of the button of the page.
+    pub color: LPXColor,
+    pub pads: Vec<(LpxPad, Box<dyn PadHandler>)>,
+}
+
+impl ProgrammerPage {
+    pub fn from_config(config: &ProgrammerPageConfig) -> Self {
+        let handler = |pad: &ProgrammerPadConfig| -> Box<dyn PadHandler> {
+            match pad.toggle {
+                true => Box::new(TogglePad::new(pad.number(), pad.channel, pad.color, pad.color_off)),
+                false => Box::new(MomentaryPad::new(pad.number(), pad.channel, pad.color, pad.color_off)),
+            }
+        };
+        ProgrammerPage {
+            color: config.color,
+            pads: config.pads.iter().map(|pad| (LpxPad { x: pad.x, y: pad.y }, handler(pad))).collect(),
+        }
+    }
+
+    fn handler(&self, pad: LpxPad) -> Option<&dyn PadHandler> {
+        self.pads.iter().find(|(p, _)| *p == pad).map(|(_, handler)| handler.as_ref())
+    }
+}
+
+pub struct Programmer<'a> {
+    profile: &'static dyn DeviceProfile,
+    pages: Vec<ProgrammerPage>,
+    page: Cell<usize>,
+    // Pads that are held, with the page whose handler took the press (and gets the release).
+    held: RefCell<Vec<(u8, usize)>>,
+    leds: &'a LedFramebuffer,
+}
+
+impl<'a> Programmer<'a> {
+    pub fn new(profile: &'static dyn DeviceProfile, pages: Vec<ProgrammerPage>, leds: &'a LedFramebuffer) -> Self {
+        Programmer { profile, pages, page: Cell::new(0), held: RefCell::new(vec![]), leds }
+    }
+
+    // Generator: select the programmer layout and light the current page.
+    pub fn Init(&self) -> FilterChain<'_> {
+        Fork!(
+            // The layout makes the framebuffer forget the pads, so that all of them are lit.
+            Chain!(LPXSelectLayout(self.profile, DeviceLayout::Programmer), self.leds.Track()),
+            Chain!(ProgrammerLights(self), LPX())
+        )
+    }
+
+    // Patch of the programmer layout: pads go to their handlers, and the right column selects the page.
+    // Depending on the device, pads and buttons come from the DAW or the MIDI port.
+    pub fn Patch(&self) -> FilterChain<'_> {
+        let mut filters: Vec<Box<dyn FilterTrait + '_>> = vec![
+            Box::new(Fork!(
+                Chain!(Fork!(LPXFilter(), LPXMidiFilter()), ProgrammerPress(self), CTR()),
+                Chain!(Fork!(LPXFilter(), LPXMidiFilter()), TypeFilter!(Note), ProgrammerLights(self), LPX())
+            )),
+            // Show the state that the Controller sends back.
+            Box::new(Chain!(CTRFilter(), self.Store(), ProgrammerLights(self), LPX())),
+        ];
+        for i in 0..self.pages.len() {
+            if let Some(button) = LpxButton::scene(i as u8) {
+                filters.push(Box::new(Chain!(
+                    Fork!(LPXFilter(), LPXMidiFilter()), LPXPressFilter(button.into()),
+                    ProgrammerSelect(self, i), ProgrammerLights(self), LPX()
+                )));
+            }
+        }
+        FilterChain::new(ConnectionType::Fork, filters)
+    }
+
+    // Generator: leave the programmer layout, releasing the pads that are still held.
+    pub fn Exit(&self) -> FilterChain<'_> {
+        Chain!(ProgrammerRelease(self), CTR())
+    }
+
+    // Filter: follow the state that the Controller sends back, for all pages (passing all events).
+    pub fn Store(&self) -> ProgrammerStore<'_, 'a> {
+        ProgrammerStore(self)
+    }
+
+    fn handler(&self, page: usize, note: u8) -> Option<&dyn PadHandler> {
+        LpxPad::from_number(note).and_then(|pad| self.pages.get(page)?.handler(pad))
+    }
+
+    // Lighting of the pads of the current page and of the page buttons.
+    fn lights(&self) -> Vec<(u8, LPXLight)> {
+        let page = self.pages.get(self.page.get());
+        let mut lights: Vec<(u8, LPXLight)> = LpxPad::all().map(|pad| {
+            let light = page.and_then(|page| page.handler(pad)).map_or(LPXLight::Static(Black as u8), |handler| handler.light());
+            (pad.number(), light)
+        }).collect();
+        for row in 0..8 {
+            let color = match self.pages.get(row) {
+                Some(page) if row == self.page.get() => page.color,
+                Some(_) => Softwhite,
+                None => Black,
+            };
+            if let Some(button) = LpxButton::scene(row as u8) {
+                lights.push((button.number(), LPXLight::Static(color as u8)));
+            }
+        }
+        lights
+    }
+}
+
+// Generator: lighting of the LEDs of the programmer layout that changed.
+pub struct ProgrammerLights<'a, 'b>(&'a Programmer<'b>);
+impl<'a, 'b> FilterTrait for ProgrammerLights<'a, 'b> {
+    fn run(&self, evs: &mut EventStream) {
+        if evs.is_empty() { return; }
+        evs.clear();
+        evs.extend(self.0.leds.set_lights(&self.0.lights()));
+    }
+}
+
+// Filter: replace pad presses by the events of their handlers on the current page, and releases by
+// those of the handler that took the press (also when the page changed in the meantime).
+pub struct ProgrammerPress<'a, 'b>(&'a Programmer<'b>);
+impl<'a, 'b> FilterTrait for ProgrammerPress<'a, 'b> {
+    fn run(&self, evs: &mut EventStream) {
+        let mut held = self.0.held.borrow_mut();
+        let mut out = vec![];
+        for ev in evs.iter() {
+            let (note, pressed) = match ev {
+                Event::NoteOn(ev) if ev.channel == 1 => (ev.note, ev.velocity > 0),
+                Event::NoteOff(ev) if ev.channel == 1 => (ev.note, false),
+                _ => continue,
+            };
+            let page = match held.iter().position(|(n, _)| *n == note) {
+                Some(i) => held.remove(i).1,
+                // A release without a press, e.g. of a pad pressed before the layout was entered.
+                None if !pressed => continue,
+                None => self.0.page.get(),
+            };
+            if let Some(handler) = self.0.handler(page, note) {
+                if pressed { held.push((note, page)); }
+                out.extend(handler.press(pressed));
+            }
+        }
+        evs.clear();
+        evs.extend(out);
+    }
+}
+
+// Generator: the releases of the pads that are held.
+pub struct ProgrammerRelease<'a, 'b>(&'a Programmer<'b>);
+impl<'a, 'b> FilterTrait for ProgrammerRelease<'a, 'b> {
+    fn run(&self, evs: &mut EventStream) {
+        if evs.is_empty() { return; }
+        evs.clear();
+        for (note, page) in self.0.held.borrow_mut().drain(..) {
+            if let Some(handler) = self.0.handler(page, note) {
+                evs.extend(handler.press(false));
+            }
+        }
+    }
+}
+
+// Filter: select a page, passing events when it wasn't selected yet.
+pub struct ProgrammerSelect<'a, 'b>(&'a Programmer<'b>, usize);
+impl<'a, 'b> FilterTrait for ProgrammerSelect<'a, 'b> {
+    fn run(&self, evs: &mut EventStream) {
+        if self.0.page.get() == self.1 {
+            evs.clear();
+        } else if !evs.is_empty() {
+            self.0.page.set(self.1);
+        }
+    }
+}
+
+// Filter: pass events to the handlers of all pages, so that they follow the Controller.
+pub struct ProgrammerStore<'a, 'b>(&'a Programmer<'b>);
+impl<'a, 'b> FilterTrait for ProgrammerStore<'a, 'b> {
+    fn run(&self, evs: &mut EventStream) {
+        for ev in evs.iter() {
+            for (_, handler) in self.0.pages.iter().flat_map(|page| page.pads.iter()) {
+                handler.feedback(ev);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::profile::LaunchpadX;
+
+    #[test]
+    fn programmer() {
+        let leds = LedFramebuffer::new(&LaunchpadX, Duration::from_millis(20), 400);
+        let light = |color: LPXColor| LPXLight::Static(color as u8);
+        let pages = vec![
+            ProgrammerPage { color: Magenta, pads: vec![
+                (LpxPad { x: 0, y: 0 }, Box::new(MomentaryPad::new(NoteOrCtrl::Ctrl(20), 1, light(Green), light(Darkgrey))) as _),
+                (LpxPad { x: 1, y: 0 }, Box::new(TogglePad::new(NoteOrCtrl::Note(60), 2, light(Red), light(Black))) as _),
+            ]},
+            ProgrammerPage { color: Sky, pads: vec![] },
+        ];
+        let programmer = Programmer::new(&LaunchpadX, pages, &leds);
+        let lighting = |lights: &[(u8, LPXColor)]| {
+            LpxSysEx::Lighting(lights.iter().map(|(led, color)| (*led, LPXLight::Static(*color as u8))).collect()).to_bytes()
+        };
+        let run = |filter: &dyn FilterTrait, evs: Vec<Event<'static>>| {
+            let mut evs = EventStream::from(evs);
+            filter.run(&mut evs);
+            evs.iter().map(|ev| match ev {
+                Event::SysEx(ev) => Err(ev.data.to_vec()),
+                ev => Ok(ev.clone()),
+            }).collect::<Vec<_>>()
+        };
+
+        let init = run(&programmer.Init(), vec![CtrlEvent(0, 1, 0, 0)]);
+        assert_eq!(init[0], Err(LpxSysEx::SelectLayout(127).to_bytes()));
+        // All pads and the right buttons, in the order of the LEDs.
+        let mut lights: Vec<(u8, LPXColor)> = LPX_PADS.iter().map(|pad| (*pad, Black)).collect();
+        lights[0].1 = Darkgrey;
+        lights.extend((2..8).filter_map(LpxButton::scene).map(|button| (button.number(), Black)));
+        lights.extend(&[(79, Softwhite), (89, Magenta)]);
+        lights.sort_unstable_by_key(|(led, _)| *led);
+        assert_eq!(init[1], Err(lighting(&lights)));
+
+        // Pads send their events to the Controller, and show their state.
+        let patch = programmer.Patch();
+        let pad = |note, color: LPXColor| Ok(NoteOnEvent(1, 1, note, color as u8));
+        assert_eq!(run(&patch, vec![NoteOnEvent(1, 1, 11, 100)]), vec![Ok(CtrlEvent(2, 1, 20, 127)), pad(11, Green)]);
+        assert_eq!(run(&patch, vec![NoteOnEvent(1, 1, 11, 0)]), vec![Ok(CtrlEvent(2, 1, 20, 0)), pad(11, Darkgrey)]);
+        assert_eq!(run(&patch, vec![NoteOnEvent(4, 1, 12, 100)]), vec![Ok(NoteOnEvent(2, 2, 60, 127)), pad(12, Red)]);
+        assert_eq!(run(&patch, vec![NoteOnEvent(4, 1, 12, 0)]), vec![]);
+        // A toggle follows the Controller.
+        assert_eq!(run(&patch, vec![NoteOnEvent(2, 2, 60, 0)]), vec![pad(12, Black)]);
+
+        // The right buttons select a page, a pad held meanwhile is released by the handler that took the press.
+        assert_eq!(run(&patch, vec![NoteOnEvent(1, 1, 11, 100)]), vec![Ok(CtrlEvent(2, 1, 20, 127)), pad(11, Green)]);
+        assert_eq!(run(&patch, vec![CtrlEvent(1, 1, 79, 127)]), vec![Err(lighting(&[(11, Black), (79, Sky), (89, Softwhite)]))]);
+        assert_eq!(run(&patch, vec![CtrlEvent(1, 1, 79, 127)]), vec![]);
+        assert_eq!(run(&patch, vec![NoteOnEvent(1, 1, 11, 0)]), vec![Ok(CtrlEvent(2, 1, 20, 0))]);
+        assert_eq!(run(&patch, vec![NoteOnEvent(1, 1, 11, 100)]), vec![]);
+        assert_eq!(run(&patch, vec![NoteOnEvent(1, 1, 11, 0)]), vec![]);
+
+        // Leaving the layout releases the pads that are held.
+        assert_eq!(run(&patch, vec![CtrlEvent(1, 1, 89, 127)]), vec![Err(lighting(&[(11, Darkgrey), (79, Softwhite), (89, Magenta)]))]);
+        assert_eq!(run(&patch, vec![NoteOnEvent(1, 1, 11, 100)]), vec![Ok(CtrlEvent(2, 1, 20, 127)), pad(11, Green)]);
+        assert_eq!(run(&programmer.Exit(), vec![CtrlEvent(0, 1, 0, 0)]), vec![Ok(CtrlEvent(2, 1, 20, 0))]);
+        assert_eq!(run(&programmer.Exit(), vec![CtrlEvent(0, 1, 0, 0)]), vec![]);
+    }
+}